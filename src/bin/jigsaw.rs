@@ -0,0 +1,83 @@
+//! Cut a plain PBM bitmap into day 20 tiles and print them as a puzzle input
+//!
+//! jigsaw [--size N] [--borders keep|unique|pool:N] [--seed N] [--truth FILE] [BITMAP]
+//!
+//! The bitmap is read from stdin without a file. `--truth` also writes where each tile
+//! comes from, as `Jigsaw::write_truth` does.
+
+use std::io::{Read, Write};
+
+use anyhow::{anyhow, Context, Result};
+
+use advent2020::day20::{BitGrid, Borders, Jigsaw};
+
+struct Options {
+    size: usize,
+    borders: Borders,
+    seed: u64,
+    truth: Option<String>,
+    bitmap: Option<String>,
+}
+
+fn parse_borders(s: &str) -> Result<Borders> {
+    match s {
+        "keep" => Ok(Borders::Keep),
+        "unique" => Ok(Borders::Unique),
+        _ => {
+            let patterns = s
+                .strip_prefix("pool:")
+                .ok_or_else(|| anyhow!("Unknown borders {:?}", s))?;
+            Ok(Borders::Pool(patterns.parse().context("Pool size")?))
+        }
+    }
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options> {
+    let mut options = Options {
+        size: 10,
+        borders: Borders::Keep,
+        seed: 0,
+        truth: None,
+        bitmap: None,
+    };
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| anyhow!("Missing value for {}", arg))
+        };
+        match arg.as_str() {
+            "--size" => options.size = value()?.parse().context("Tile size")?,
+            "--borders" => options.borders = parse_borders(&value()?)?,
+            "--seed" => options.seed = value()?.parse().context("Seed")?,
+            "--truth" => options.truth = Some(value()?),
+            _ if arg.starts_with("--") => return Err(anyhow!("Unknown option {}", arg)),
+            _ if options.bitmap.is_none() => options.bitmap = Some(arg),
+            _ => return Err(anyhow!("Unexpected argument {}", arg)),
+        }
+    }
+    Ok(options)
+}
+
+fn main() -> Result<()> {
+    let options = parse_args(std::env::args().skip(1))?;
+    let pbm = match &options.bitmap {
+        Some(path) => std::fs::read_to_string(path).with_context(|| format!("Reading {}", path))?,
+        None => {
+            let mut s = String::new();
+            std::io::stdin().read_to_string(&mut s)?;
+            s
+        }
+    };
+    let bitmap = BitGrid::read_pbm(&pbm)?;
+    let jigsaw = Jigsaw::cut(&bitmap, options.size, options.borders, options.seed)?;
+
+    let stdout = std::io::stdout();
+    let mut out = stdout.lock();
+    jigsaw.write_input(&mut out)?;
+    out.flush()?;
+    if let Some(path) = &options.truth {
+        let mut file = std::fs::File::create(path).with_context(|| format!("Creating {}", path))?;
+        jigsaw.write_truth(&mut file)?;
+    }
+    Ok(())
+}
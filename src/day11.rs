@@ -2,6 +2,12 @@ use anyhow::{anyhow, Result};
 use aoc_runner_derive::{aoc, aoc_generator};
 use std::convert::TryFrom;
 
+use hashbrown::HashSet;
+
+use crate::life::{
+    Bitboard, Boundary, Cycle, Dense, FiniteState, Neighbourhood, Rule, Schedule, Square,
+};
+
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum Space {
     Floor,
@@ -31,84 +37,131 @@ impl ToString for Space {
     }
 }
 
-#[derive(Clone)]
 pub struct Layout {
     grid: Vec<Space>,
-    swaps: Vec<usize>,
     part2: bool,
-    rows: isize,
-    cols: isize,
+    rows: usize,
+    cols: usize,
+    // Replaces the puzzle rule
+    rule: Option<Rule>,
+    // What lies past the edges of the map
+    boundary: Boundary,
+    // Seats as cells, built on the first step
+    life: Option<Box<dyn FiniteState<Cell = (usize, usize)>>>,
+    // Layouts gone through while iterating with `life`, reset with it
+    seen: HashSet<Vec<u64>>,
+}
+
+/// The automaton is not cloned, the clone builds it again from its grid and forgets
+/// the layouts gone through
+impl Clone for Layout {
+    fn clone(&self) -> Self {
+        Self {
+            grid: self.grid.clone(),
+            part2: self.part2,
+            rows: self.rows,
+            cols: self.cols,
+            rule: self.rule.clone(),
+            boundary: self.boundary,
+            life: None,
+            seen: HashSet::new(),
+        }
+    }
 }
 
 impl std::fmt::Debug for Layout {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let s: Vec<String> = self.grid.iter().map(|s| s.to_string()).collect();
-        let s: Vec<String> = s
-            .chunks_exact(self.cols as usize)
-            .map(|s| s.join(""))
-            .collect();
+        let s: Vec<String> = s.chunks_exact(self.cols).map(|s| s.join("")).collect();
         write!(f, "\n{}\n", s.join("\n"))
     }
 }
 
 impl Layout {
-    const DIRS: [(isize, isize); 8] = [
-        (-1, -1),
-        (-1, 0),
-        (-1, 1),
-        (0, -1),
-        (0, 1),
-        (1, -1),
-        (1, 0),
-        (1, 1),
-    ];
-
-    fn bound_check(&self, x: isize, y: isize) -> bool {
-        (0..self.rows).contains(&x) && (0..self.cols).contains(&y)
-    }
-
-    fn get(&self, x: isize, y: isize) -> Option<&Space> {
-        if !self.bound_check(x, y) {
-            return None;
+    /// Empty seats without occupied neighbours fill up, occupied seats empty from 4 occupied
+    /// neighbours (5 in part 2). Part 2 looks past the floor.
+    /// Adjacent seats within walls are counted a row of words at a time.
+    fn automaton(&self) -> Box<dyn FiniteState<Cell = (usize, usize)>> {
+        self.scheduled(Schedule::Synchronous)
+    }
+
+    /// Only synchronous updates are done by words
+    fn scheduled(&self, schedule: Schedule) -> Box<dyn FiniteState<Cell = (usize, usize)>> {
+        let (neighbourhood, threshold) = if self.part2 {
+            (Neighbourhood::LineOfSight, 5)
+        } else {
+            (Neighbourhood::Adjacent, 4)
+        };
+        let space = |x: usize, y: usize| self.grid[x * self.cols + y];
+        let seats = Square::new(
+            self.rows,
+            self.cols,
+            |x, y| space(x, y) != Space::Floor,
+            neighbourhood,
+        )
+        .with_boundary(self.boundary);
+        let survival: Vec<usize> = (0..threshold).collect();
+        let rule = self
+            .rule
+            .clone()
+            .unwrap_or_else(|| Rule::new(&[0], &survival));
+        let occupied = |(x, y)| space(x, y) == Space::Seat(true);
+        match Bitboard::new(&seats, rule.clone(), occupied) {
+            Ok(bits) if schedule == Schedule::Synchronous => Box::new(bits),
+            _ => Box::new(
+                Dense::new(&seats, rule, occupied)
+                    .expect("Seats fit in an adjacency")
+                    .with_schedule(schedule),
+            ),
         }
-        self.grid.get((x * self.cols + y) as usize)
     }
 
-    fn map_index(&self, idx: usize) -> (isize, isize) {
-        let idx = idx as isize;
-        (idx / self.cols, idx % self.cols)
+    /// Use `rule` instead of the puzzle's, seats are live when occupied
+    pub fn with_rule(mut self, rule: Rule) -> Self {
+        self.rule = Some(rule);
+        self.life = None;
+        self.seen.clear();
+        self
     }
 
-    fn count_adjacent_occupied(&self, idx: usize) -> usize {
-        let (x, y) = self.map_index(idx);
-        Self::DIRS
-            .iter()
-            .filter_map(move |(dx, dy)| self.get(x + dx, y + dy))
-            .filter(|s| matches!(s, Space::Seat(true)))
-            .count()
+    /// Map edges that wrap around or reflect instead of showing nothing
+    pub fn with_boundary(mut self, boundary: Boundary) -> Self {
+        self.boundary = boundary;
+        self.life = None;
+        self.seen.clear();
+        self
     }
 
-    fn count_visible_occupied(&self, idx: usize) -> usize {
-        let (x, y) = self.map_index(idx);
-        Self::DIRS
-            .iter()
-            .filter_map(move |(dx, dy)| {
-                let m = (1..).find(|m| {
-                    let space = self.get(x + m * dx, y + m * dy);
-                    space.is_none() || matches!(space, Some(Space::Seat(_)))
-                })?;
-                self.get(x + m * dx, y + m * dy)
-            })
-            .filter(|s| matches!(s, Space::Seat(true)))
-            .count()
+    /// Transient, period and occupied seats of the cycle the layout ends up in
+    pub fn find_cycle(&self) -> Cycle {
+        self.automaton().find_cycle()
     }
 
-    fn count_occupied_neighbors(&self, idx: usize) -> usize {
-        if self.part2 {
-            self.count_visible_occupied(idx)
-        } else {
-            self.count_adjacent_occupied(idx)
-        }
+    /// Update the seats with each schedule until they stop changing, for at most
+    /// `max_rounds` rounds, and compare where they settle with synchronous updates
+    pub fn settle(&self, schedules: &[Schedule], max_rounds: usize) -> Vec<Settling> {
+        let run = |schedule| {
+            let mut life = self.scheduled(schedule);
+            let rounds = (0..max_rounds).find(|_| life.step() == 0);
+            let mut occupied = life.live_cells();
+            occupied.sort_unstable();
+            (rounds, occupied)
+        };
+        let reference = run(Schedule::Synchronous);
+        schedules
+            .iter()
+            .map(|&schedule| {
+                let (rounds, occupied) = run(schedule);
+                Settling {
+                    schedule,
+                    rounds,
+                    occupied: occupied.len(),
+                    same_as_synchronous: rounds.is_some()
+                        && reference.0.is_some()
+                        && occupied == reference.1,
+                }
+            })
+            .collect()
     }
 
     fn count_occupied_seats(&self) -> usize {
@@ -119,41 +172,72 @@ impl Layout {
     }
 }
 
+/// Where the seats end up with an update schedule
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Settling {
+    pub schedule: Schedule,
+    /// Rounds changing some seat, none if seats still change after the limit
+    pub rounds: Option<usize>,
+    /// Occupied seats after the last round
+    pub occupied: usize,
+    /// Whether it settles with the same occupied seats as synchronous updates
+    pub same_as_synchronous: bool,
+}
+
+impl std::fmt::Display for Settling {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let schedule = format!("{:?}", self.schedule);
+        match self.rounds {
+            Some(rounds) => write!(
+                f,
+                "{:<20} {:>4} rounds {:>6} occupied{}",
+                schedule,
+                rounds,
+                self.occupied,
+                if self.same_as_synchronous {
+                    ""
+                } else {
+                    ", not the synchronous fixed point"
+                }
+            ),
+            None => write!(
+                f,
+                "{:<20} not settled {:>6} occupied",
+                schedule, self.occupied
+            ),
+        }
+    }
+}
+
+/// Occupied seats after each round, until the layout stops changing or repeats
 impl Iterator for Layout {
     type Item = usize;
 
     fn next(&mut self) -> Option<Self::Item> {
-        // Part 1 rule is 4 occupied seats, part2 is 5
-        let threshold = 4 + (self.part2 as usize);
-        for idx in 0..self.grid.len() {
-            if let Space::Seat(occupied) = self.grid[idx] {
-                let cnt = self.count_occupied_neighbors(idx);
-                match (occupied, cnt) {
-                    (true, cnt) if cnt >= threshold => self.swaps.push(idx),
-                    (false, 0) => self.swaps.push(idx),
-                    _ => (),
-                }
-            }
+        if self.life.is_none() {
+            self.life = Some(self.automaton());
         }
-
-        if self.swaps.is_empty() {
-            None
-        } else {
-            for idx in self.swaps.drain(..) {
-                match self.grid.get_mut(idx) {
-                    Some(Space::Seat(occupied)) => *occupied = !*occupied,
-                    _ => (),
-                }
-            }
-            Some(debug!(self).count_occupied_seats())
+        let life = self.life.as_mut()?;
+        if self.seen.is_empty() {
+            self.seen.insert(life.packed());
+        }
+        if life.step() == 0 || !self.seen.insert(life.packed()) {
+            return None;
         }
+        for space in self.grid.iter_mut().filter(|s| **s != Space::Floor) {
+            *space = Space::Seat(false);
+        }
+        for (x, y) in life.live_cells() {
+            self.grid[x * self.cols + y] = Space::Seat(true);
+        }
+        Some(debug!(self).count_occupied_seats())
     }
 }
 
 #[aoc_generator(day11)]
 pub fn input_parser(input: &str) -> Layout {
-    let rows = input.lines().count() as isize;
-    let cols = input.lines().next().expect("Non-empty input").len() as isize;
+    let rows = input.lines().count();
+    let cols = input.lines().next().expect("Non-empty input").len();
     let grid = input
         .lines()
         .flat_map(|s| {
@@ -164,24 +248,28 @@ pub fn input_parser(input: &str) -> Layout {
         .collect();
     Layout {
         grid,
-        swaps: Vec::new(),
         part2: false,
         rows,
         cols,
+        rule: None,
+        boundary: Boundary::Wall,
+        life: None,
+        seen: HashSet::new(),
     }
 }
 
 #[aoc(day11, part1)]
 pub fn part1(layout: &Layout) -> Option<usize> {
-    let layout = layout.clone();
-    layout.into_iter().last()
+    let cycle = layout.find_cycle();
+    Some(cycle.population[0]).filter(|_| cycle.period == 1)
 }
 
 #[aoc(day11, part2)]
 pub fn part2(layout: &Layout) -> Option<usize> {
     let mut layout = layout.clone();
     layout.part2 = true;
-    layout.into_iter().last()
+    let cycle = layout.find_cycle();
+    Some(cycle.population[0]).filter(|_| cycle.period == 1)
 }
 
 #[cfg(test)]
@@ -209,4 +297,92 @@ L.LLLLL.LL";
     fn test_part2() {
         assert_eq!(part2(&input_parser(TESTCASE)), Some(26))
     }
+
+    #[test]
+    fn test_cycle() {
+        let layout = input_parser(TESTCASE);
+        let cycle = layout.find_cycle();
+        assert_eq!((cycle.transient, cycle.period), (5, 1));
+        assert_eq!(cycle.population, vec![37]);
+        assert_eq!(layout.into_iter().last(), Some(37));
+
+        // Occupied seats always empty: every seat blinks
+        let blinking = input_parser("L.L\n.L.").with_rule("B0/S".parse().unwrap());
+        let cycle = blinking.find_cycle();
+        assert_eq!((cycle.transient, cycle.period), (0, 2));
+        assert_eq!(cycle.population, vec![0, 3]);
+        assert_eq!(blinking.collect::<Vec<_>>(), vec![3]);
+
+        // Layouts seen with the puzzle rule are not repeats with another rule
+        let mut layout = input_parser(TESTCASE);
+        assert_eq!(layout.by_ref().count(), 5);
+        let emptying = layout.with_rule("B/S".parse().unwrap());
+        assert_eq!(emptying.collect::<Vec<_>>(), vec![0]);
+    }
+
+    #[test]
+    fn test_settle() {
+        let schedules = [
+            Schedule::Synchronous,
+            Schedule::Sweep,
+            Schedule::Runs(10),
+            Schedule::Runs(100),
+        ];
+        let settled: Vec<_> = input_parser(TESTCASE)
+            .settle(&schedules, 100)
+            .into_iter()
+            .map(|s| (s.rounds, s.occupied, s.same_as_synchronous))
+            .collect();
+        // A single run of every seat is synchronous
+        assert_eq!(
+            settled,
+            vec![
+                (Some(5), 37, true),
+                (Some(1), 23, false),
+                (Some(2), 39, false),
+                (Some(5), 37, true)
+            ]
+        );
+        // A seed always draws the same orders, another one settles elsewhere
+        let random = [
+            Schedule::Random(3),
+            Schedule::Random(3),
+            Schedule::Random(12),
+        ];
+        let settled: Vec<_> = input_parser(TESTCASE)
+            .settle(&random, 100)
+            .into_iter()
+            .map(|s| (s.rounds, s.occupied, s.same_as_synchronous))
+            .collect();
+        assert_eq!(
+            settled,
+            vec![
+                (Some(1), 18, false),
+                (Some(1), 18, false),
+                (Some(1), 22, false)
+            ]
+        );
+        // Not compared with the synchronous seats before they settle
+        let unsettled = input_parser(TESTCASE).settle(&schedules, 3);
+        assert!(unsettled.iter().all(|s| !s.same_as_synchronous));
+        // Lonely seats fill up: both at once blink forever, one after the other the
+        // first fills up and keeps the second empty
+        let pair = input_parser("LL").with_rule("B0/S0".parse().unwrap());
+        let settled = pair.settle(&[Schedule::Synchronous, Schedule::Sweep], 100);
+        assert_eq!(settled[0].rounds, None);
+        assert_eq!((settled[1].rounds, settled[1].occupied), (Some(1), 1));
+    }
+
+    #[test]
+    fn test_boundary() {
+        let room = input_parser("LLL\nLLL\nLLL");
+        let cycle = |boundary| {
+            let cycle = room.clone().with_boundary(boundary).find_cycle();
+            (cycle.period, cycle.population)
+        };
+        assert_eq!(cycle(Boundary::Wall), (1, vec![4]));
+        // Every seat has 8 neighbours, all of them fill up and leave together
+        assert_eq!(cycle(Boundary::Torus), (2, vec![0, 9]));
+        assert_eq!(cycle(Boundary::Mirror), (2, vec![0, 9]));
+    }
 }
@@ -1,15 +1,18 @@
+use anyhow::Result;
 use aoc_runner_derive::{aoc, aoc_generator};
-use hashbrown::HashSet; // 2x faster than with std::collections::HashSet;
-use itertools::{iproduct, Itertools};
+use hashbrown::HashMap;
+use std::fmt::Display;
+
+use crate::life::{Adaptive, Array, Automaton, Lattice, Rule, Sparse, Symmetric};
 
 type Int = isize;
-type Coord3D = (Int, Int, Int);
-type Grid3D = HashSet<Coord3D>;
 
-const DIRS: [Int; 3] = [-1, 0, 1];
+/// Cycles of the boot process
+pub const CYCLES: usize = 6;
 
-#[aoc_generator(day17, part1)]
-pub fn input_parser_part1(input: &str) -> Grid3D {
+/// Active cubes of the initial slice, on the first two axes
+#[aoc_generator(day17)]
+pub fn input_parser(input: &str) -> Vec<(Int, Int)> {
     input
         .lines()
         .enumerate()
@@ -17,88 +20,244 @@ pub fn input_parser_part1(input: &str) -> Grid3D {
             s.chars()
                 .enumerate()
                 .filter(|(_, c)| *c == '#')
-                .map(move |(y, _)| (x as Int, y as Int, 0))
+                .map(move |(y, _)| (x as Int, y as Int))
         })
         .collect()
 }
 
-fn get_neighbors_3d(cube: Coord3D) -> impl Iterator<Item = Coord3D> {
-    iproduct!(&DIRS, &DIRS, &DIRS)
-        .filter(|(x, y, z)| !(**x == 0 && **y == 0 && **z == 0))
-        .map(move |(x, y, z)| (cube.0 + x, cube.1 + y, cube.2 + z))
+/// Conway's rule: active cubes stay active with 2 or 3 active neighbours,
+/// inactive cubes become active with exactly 3
+fn conway() -> Rule {
+    Rule::new(&[3], &[2, 3])
 }
 
-#[aoc(day17, part1)]
-pub fn part1(grid: &Grid3D) -> usize {
-    let mut grid = grid.clone();
-    grid = (0..6).fold(grid, |grid, _| {
-        grid.iter()
-            .copied()
-            .flat_map(get_neighbors_3d) // get all neighbors to visit
-            .unique() // remove duplicates
-            .filter(|c| {
-                let active_neighbors = get_neighbors_3d(*c)
-                    .filter(|neighbor| grid.contains(neighbor))
-                    .count();
-                match active_neighbors {
-                    2 if grid.contains(c) => true, // if active and 2 neighbors are active, active
-                    3 => true,  // if active/inactive and 3 neighbors are active, active
-                    _ => false, // otherwise inactive
-                }
-            })
-            .collect()
+/// How the active cubes are stored
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Every active cube
+    Sparse,
+    /// One active cube per orbit under the symmetries of the axes above the slice: the
+    /// slice is at 0 on all of them, so any can be reflected or swapped with another
+    Symmetric,
+    /// Every cube of the bounding box of the active ones
+    Dense,
+    /// Sparse or dense, whichever is cheaper for the density of the active cubes
+    Auto,
+}
+
+/// Automaton starting from `slice`, lying at 0 on the other axes
+fn automaton<const N: usize>(
+    slice: &[(Int, Int)],
+    mode: Mode,
+) -> Result<Box<dyn Automaton<Cell = [Int; N]>>> {
+    assert!(N >= 2, "The initial slice needs 2 dimensions");
+    let cubes = slice.iter().map(|&(x, y)| {
+        let mut cube = [0; N];
+        cube[0] = x;
+        cube[1] = y;
+        cube
     });
+    Ok(match mode {
+        Mode::Sparse => Box::new(Sparse::new(Lattice::<N>, conway(), cubes)?),
+        Mode::Symmetric => Box::new(Symmetric::new(conway(), 2, cubes)?),
+        Mode::Dense => Box::new(Array::new(conway(), cubes)?),
+        Mode::Auto => Box::new(Adaptive::new(conway(), cubes)?),
+    })
+}
 
-    grid.len()
+/// Active cubes after `cycles` cycles in `N` dimensions, fails when the active cubes
+/// grow too far apart to store with `mode`
+pub fn boot<const N: usize>(slice: &[(Int, Int)], cycles: usize, mode: Mode) -> Result<usize> {
+    automaton::<N>(slice, mode)?.try_run(cycles)
 }
 
-// Part 2
+/// Active cubes before the first cycle and after each of the `cycles` cycles
+pub fn boot_states<const N: usize>(
+    slice: &[(Int, Int)],
+    cycles: usize,
+    mode: Mode,
+) -> Result<Vec<Pocket<N>>> {
+    let mut life = automaton::<N>(slice, mode)?;
+    let mut states = vec![Pocket::new(life.live_cells())];
+    for _ in 0..cycles {
+        life.try_step()?;
+        states.push(Pocket::new(life.live_cells()));
+    }
+    Ok(states)
+}
 
-type Coord4D = (Int, Int, Int, Int);
-type Grid4D = HashSet<Coord4D>;
+/// Coordinates of a slice on the axes above `y`, and its active `(x, y)`
+type Slice = (Vec<Int>, Vec<(Int, Int)>);
 
-#[aoc_generator(day17, part2)]
-pub fn input_parser_part2(input: &str) -> Grid4D {
-    input
-        .lines()
-        .enumerate()
-        .flat_map(|(x, s)| {
-            s.chars()
-                .enumerate()
-                .filter(|(_, c)| *c == '#')
-                .map(move |(y, _)| (x as Int, y as Int, 0, 0))
-        })
-        .collect()
+/// Names of the axes, those after `y` are the ones of the slices
+const AXES: [&str; 8] = ["x", "y", "z", "w", "v", "u", "t", "s"];
+
+fn axis(i: usize) -> String {
+    AXES.get(i)
+        .map_or_else(|| format!("a{}", i), |a| a.to_string())
 }
 
-fn get_neighbors_4d(cube: Coord4D) -> impl Iterator<Item = Coord4D> {
-    iproduct!(&DIRS, &DIRS, &DIRS, &DIRS)
-        .filter(|(x, y, z, w)| !(**x == 0 && **y == 0 && **z == 0 && **w == 0))
-        .map(move |(x, y, z, w)| (cube.0 + x, cube.1 + y, cube.2 + z, cube.3 + w))
+/// Active cubes of a pocket dimension, seen as 2D slices along `x` and `y`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pocket<const N: usize> {
+    // In the puzzle's order
+    slices: Vec<Slice>,
+    // Bounding box of the active cubes on `x` and `y`, empty with no cube
+    rows: std::ops::Range<Int>,
+    cols: std::ops::Range<Int>,
 }
 
-#[aoc(day17, part2)]
-pub fn part2(grid: &Grid4D) -> usize {
-    let mut grid = grid.clone();
-    grid = (0..6).fold(grid, |grid, _| {
-        grid.iter()
-            .copied()
-            .flat_map(get_neighbors_4d) // get all neighbors to visit
-            .unique() // remove duplicates
-            .filter(|c| {
-                let active_neighbors = get_neighbors_4d(*c)
-                    .filter(|neighbor| grid.contains(neighbor))
-                    .count();
-                match active_neighbors {
-                    2 if grid.contains(c) => true, // if active and 2 neighbors are active, active
-                    3 => true,  // if active/inactive and 3 neighbors are active, active
-                    _ => false, // otherwise inactive
+impl<const N: usize> Pocket<N> {
+    pub fn new(cubes: impl IntoIterator<Item = [Int; N]>) -> Self {
+        let mut slices: HashMap<Vec<Int>, Vec<(Int, Int)>> = HashMap::new();
+        for cube in cubes {
+            slices
+                .entry(cube[2..].to_vec())
+                .or_default()
+                .push((cube[0], cube[1]));
+        }
+        let mut slices: Vec<_> = slices.into_iter().collect();
+        // The last axis varies slowest, like "z=-1, w=-1" then "z=0, w=-1"
+        slices.sort_unstable_by(|(a, _), (b, _)| a.iter().rev().cmp(b.iter().rev()));
+        slices
+            .iter_mut()
+            .for_each(|(_, cubes)| cubes.sort_unstable());
+        let bounds = |coord: fn(&(Int, Int)) -> Int| {
+            let all = slices.iter().flat_map(|(_, cubes)| cubes.iter().map(coord));
+            match (all.clone().min(), all.max()) {
+                (Some(min), Some(max)) => min..max + 1,
+                _ => 0..0,
+            }
+        };
+        Self {
+            rows: bounds(|c| c.0),
+            cols: bounds(|c| c.1),
+            slices,
+        }
+    }
+
+    pub fn active(&self) -> usize {
+        self.slices.iter().map(|(_, cubes)| cubes.len()).sum()
+    }
+
+    /// "z=-1, w=0" for the slice at those coordinates
+    pub fn label(coords: &[Int]) -> String {
+        coords
+            .iter()
+            .enumerate()
+            .map(|(i, c)| format!("{}={}", axis(i + 2), c))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// Slices with active cubes, from the first in the puzzle's order, and their count
+    pub fn counts(&self) -> impl Iterator<Item = (&[Int], usize)> {
+        self.slices
+            .iter()
+            .map(|(coords, cubes)| (&coords[..], cubes.len()))
+    }
+
+    /// Slice as rows of the bounding box
+    fn rows(&self, cubes: &[(Int, Int)]) -> Vec<Vec<bool>> {
+        let (rows, cols) = (self.rows.len(), self.cols.len());
+        let mut grid = vec![vec![false; cols]; rows];
+        for (x, y) in cubes {
+            grid[(x - self.rows.start) as usize][(y - self.cols.start) as usize] = true;
+        }
+        grid
+    }
+
+    /// Plain PBM (P1) of the slices side by side, `per_row` of them on each row of
+    /// the sheet, one white pixel apart. Active cubes are black.
+    pub fn write_pbm(&self, w: &mut impl std::io::Write, per_row: usize) -> std::io::Result<()> {
+        let per_row = per_row.max(1);
+        let (rows, cols) = (self.rows.len(), self.cols.len());
+        let sheet_rows = (self.slices.len() + per_row - 1) / per_row;
+        let (height, width) = (
+            (sheet_rows * (rows + 1)).saturating_sub(1),
+            (self.slices.len().min(per_row) * (cols + 1)).saturating_sub(1),
+        );
+        let mut pixels = vec![vec![b'0'; width]; height];
+        for (i, (_, cubes)) in self.slices.iter().enumerate() {
+            let (x0, y0) = (i / per_row * (rows + 1), i % per_row * (cols + 1));
+            for (x, row) in self.rows(cubes).iter().enumerate() {
+                for (y, _) in row.iter().enumerate().filter(|(_, active)| **active) {
+                    pixels[x0 + x][y0 + y] = b'1';
                 }
-            })
-            .collect()
-    });
+            }
+        }
+        writeln!(w, "P1\n{} {}", width, height)?;
+        for row in pixels {
+            // Plain netpbm lines are at most 70 characters
+            for chunk in row.chunks(70) {
+                w.write_all(chunk)?;
+                writeln!(w)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Slices as in the puzzle statement, cropped to the active cubes
+impl<const N: usize> Display for Pocket<N> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, (coords, cubes)) in self.slices.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            writeln!(f, "{}", Self::label(coords))?;
+            for row in self.rows(cubes) {
+                let row: String = row.iter().map(|a| if *a { '#' } else { '.' }).collect();
+                writeln!(f, "{}", row)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Table of the active cubes in each slice after each cycle, with their total
+pub fn summary<const N: usize>(states: &[Pocket<N>]) -> String {
+    let mut counts: HashMap<&[Int], Vec<usize>> = HashMap::new();
+    for (cycle, state) in states.iter().enumerate() {
+        for (coords, count) in state.counts() {
+            counts
+                .entry(coords)
+                .or_insert_with(|| vec![0; states.len()])[cycle] = count;
+        }
+    }
+    let mut slices: Vec<_> = counts.into_iter().collect();
+    slices.sort_unstable_by(|(a, _), (b, _)| a.iter().rev().cmp(b.iter().rev()));
+    let totals: Vec<usize> = states.iter().map(Pocket::active).collect();
+    let rows = slices
+        .into_iter()
+        .map(|(coords, counts)| (Pocket::<N>::label(coords), counts))
+        .chain(std::iter::once(("total".to_string(), totals)));
+
+    let labels = std::iter::once("cycle".len())
+        .chain(rows.clone().map(|(label, _)| label.len()))
+        .max()
+        .unwrap_or(0);
+    let mut table = format!("{:<width$}", "cycle", width = labels);
+    for cycle in 0..states.len() {
+        table += &format!(" {:>6}", cycle);
+    }
+    for (label, counts) in rows {
+        table += &format!("\n{:<width$}", label, width = labels);
+        for count in counts {
+            table += &format!(" {:>6}", count);
+        }
+    }
+    table
+}
+
+#[aoc(day17, part1)]
+pub fn part1(slice: &[(Int, Int)]) -> Result<usize> {
+    boot::<3>(slice, CYCLES, Mode::Symmetric)
+}
 
-    grid.len()
+#[aoc(day17, part2)]
+pub fn part2(slice: &[(Int, Int)]) -> Result<usize> {
+    boot::<4>(slice, CYCLES, Mode::Symmetric)
 }
 
 #[cfg(test)]
@@ -112,11 +271,79 @@ mod test_day17 {
 
     #[test]
     fn test_part1() {
-        assert_eq!(part1(&input_parser_part1(TESTCASE)), 112)
+        assert_eq!(part1(&input_parser(TESTCASE)).unwrap(), 112)
     }
 
     #[test]
     fn test_part2() {
-        assert_eq!(part2(&input_parser_part2(TESTCASE)), 848)
+        assert_eq!(part2(&input_parser(TESTCASE)).unwrap(), 848)
+    }
+
+    #[test]
+    fn test_dimensions() {
+        let slice = input_parser(TESTCASE);
+        // A glider in the plane
+        assert_eq!(boot::<2>(&slice, 4, Mode::Sparse).unwrap(), 5);
+        assert_eq!(boot::<3>(&slice, 1, Mode::Sparse).unwrap(), 11);
+        assert_eq!(boot::<5>(&slice, CYCLES, Mode::Symmetric).unwrap(), 5760);
+        assert_eq!(boot::<6>(&slice, CYCLES, Mode::Symmetric).unwrap(), 35936);
+        // The full boot in 7 dimensions is in `test_timing`
+        assert_eq!(boot::<7>(&slice, 2, Mode::Auto).unwrap(), 1152);
+        assert_eq!(boot::<7>(&slice, 3, Mode::Symmetric).unwrap(), 106400);
+    }
+
+    /// Timing, run with `cargo test --release -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn test_timing() {
+        let slice = input_parser(TESTCASE);
+        let start = std::time::Instant::now();
+        assert_eq!(boot::<7>(&slice, 3, Mode::Auto).unwrap(), 106400);
+        let auto = start.elapsed();
+        let start = std::time::Instant::now();
+        assert_eq!(boot::<7>(&slice, CYCLES, Mode::Symmetric).unwrap(), 178720);
+        let symmetric = start.elapsed();
+        println!(
+            "7 dimensions: auto {:?} for 3 cycles, symmetric {:?} for {}",
+            auto, symmetric, CYCLES
+        );
+        assert!(symmetric < std::time::Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_render() {
+        let states = boot_states::<3>(&input_parser(TESTCASE), 1, Mode::Symmetric).unwrap();
+        assert_eq!(
+            states[1].to_string(),
+            "z=-1\n#..\n..#\n.#.\n\nz=0\n#.#\n.##\n.#.\n\nz=1\n#..\n..#\n.#.\n"
+        );
+        let mut pbm = Vec::new();
+        states[1].write_pbm(&mut pbm, 2).unwrap();
+        assert_eq!(
+            String::from_utf8(pbm).unwrap(),
+            "P1\n7 7\n1000101\n0010011\n0100010\n0000000\n1000000\n0010000\n0100000\n"
+        );
+        let table = summary(&states);
+        assert_eq!(
+            table,
+            "cycle      0      1\nz=-1       0      3\nz=0        5      5\nz=1        0      3\ntotal      5     11"
+        );
+        let states = boot_states::<4>(&input_parser(TESTCASE), 1, Mode::Sparse).unwrap();
+        assert!(states[1].to_string().starts_with("z=-1, w=-1\n"));
+    }
+
+    #[test]
+    fn test_modes() {
+        let slice = input_parser(TESTCASE);
+        let sparse = boot_states::<4>(&slice, CYCLES, Mode::Sparse).unwrap();
+        for mode in &[Mode::Symmetric, Mode::Dense, Mode::Auto] {
+            assert_eq!(
+                boot_states::<4>(&slice, CYCLES, *mode).unwrap(),
+                sparse,
+                "{:?}",
+                mode
+            );
+        }
+        assert_eq!(boot::<2>(&slice, 4, Mode::Symmetric).unwrap(), 5);
     }
 }
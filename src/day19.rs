@@ -1,67 +1,54 @@
+use anyhow::{anyhow, Result};
 use aoc_runner_derive::aoc;
+use hashbrown::{HashMap, HashSet};
+use itertools::Itertools;
 use regex::Regex;
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::ops::Range;
+use std::str::FromStr;
+
+use crate::utils::Rng;
 
 pub struct Input<'a> {
-    rules: Vec<&'a str>,
+    grammar: Grammar,
     messages: Vec<&'a str>,
 }
 
-pub fn input_parser(input: &str) -> Input {
-    let mut split = input.split("\n\n");
-    let rules_str = split.next().unwrap();
-    let messages: Vec<&str> = split.next().unwrap().lines().collect();
-    // Min size 43 for tests... 
-    let mut rules = vec![""; 43.max(rules_str.lines().count())];
-    rules_str.lines().for_each(|s| {
-        let mut split = s.split(": ");
-        let id = split.next().unwrap().parse::<usize>().unwrap();
-        let rule = split.next().unwrap();
-        rules[id] = rule;
-    });
-    Input { rules, messages }
-}
-
-fn build_regex(rule: &str, rules: &[&str]) -> String {
-    match rule {
-        "\"a\"" => "a".to_string(),
-        "\"b\"" => "b".to_string(),
-        "42 | 42 8" => format!("({})+", build_regex(rules[42], rules)),
-        "42 31 | 42 11 31" => {
-            let r42 = build_regex(rules[42], rules).to_string();
-            let r31 = build_regex(rules[31], rules);
-            // Rule 11 is equivalent to matching both rule 42 and 31 the same number of times.
-            // we match both groups exactly n times using regex: `(grp1){n}(grp2){n}`
-            let rule11 = |n: u8| format!("(({0}){{{n}}}({1}){{{n}}})", r42, r31, n = n);
-            format!(
-                "({}|{}|{}|{}|{})",
-                rule11(1), //    match each exactly 1 time
-                rule11(2), // or match each exactly 2 times
-                rule11(3), // or match each exactly 3 times
-                rule11(4), // ...
-                rule11(5)
-            )
-        }
-        r if r.contains("|") => {
-            let mut split = r.split(" | ");
-            let r1 = build_regex(split.next().unwrap(), rules);
-            let r2 = build_regex(split.next().unwrap(), rules);
-            format!("({}|{})", r1, r2)
-        }
-        r => r
-            .split_whitespace()
-            .map(|id_str| id_str.parse::<usize>().unwrap())
-            .map(|id| build_regex(rules[id], rules).to_string())
-            .collect(),
+/// Rules, then a blank line and the messages to check
+pub fn input_parser(input: &str) -> Result<Input<'_>> {
+    let mut lines = input.lines().map(|l| l.trim_end_matches('\r'));
+    let rules: Vec<&str> = lines
+        .by_ref()
+        .take_while(|l| !l.trim().is_empty())
+        .collect();
+    let grammar = rules.join("\n").parse()?;
+    let messages = lines.filter(|l| !l.is_empty()).collect();
+    Ok(Input { grammar, messages })
+}
+
+impl<'a> Input<'a> {
+    pub fn messages(&self) -> &[&'a str] {
+        &self.messages
+    }
+
+    pub fn grammar(&self) -> &Grammar {
+        &self.grammar
     }
 }
 
+fn count_matches(input: &Input) -> usize {
+    let recognizer = input.grammar.recognizer();
+    input
+        .messages
+        .iter()
+        .filter(|msg| recognizer.is_match(msg))
+        .count()
+}
+
 #[aoc(day19, part1, regex)]
 pub fn part1(input: &str) -> usize {
-    let Input { rules, messages } = input_parser(input);
-    let pos = build_regex(rules[0], &rules);
-    let re = Regex::new(&format!("^{}$", pos)).unwrap();
-
-    messages.iter().filter(|msg| re.is_match(*msg)).count()
+    count_matches(&input_parser(input).expect("Valid input"))
 }
 
 #[aoc(day19, part2)]
@@ -69,11 +56,1261 @@ pub fn part2(input: &str) -> usize {
     let input = input
         .replace("8: 42", "8: 42 | 42 8")
         .replace("11: 42 31", "11: 42 31 | 42 11 31");
-    let Input { rules, messages } = input_parser(&input);
-    let pos = build_regex(rules[0], &rules);
-    let re = Regex::new(&format!("^{}$", pos)).unwrap();
+    // Rule 11 is not regular anymore: the analysis picks the general matcher
+    count_matches(&input_parser(&input).expect("Valid input"))
+}
+
+// Rules and parse trees
+
+pub type RuleId = usize;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Rule {
+    Terminal(String),
+    Alternatives(Vec<Vec<RuleId>>),
+}
+
+/// Rules indexed by id, `None` for rules that are referenced but never defined.
+/// Ids are assigned in definition order, so `0: ...` on the first line gets id 0.
+#[derive(Debug, Clone, Default)]
+pub struct Grammar {
+    rules: Vec<Option<Rule>>,
+    names: Vec<String>,
+    ids: HashMap<String, RuleId>,
+    root: RuleId,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Name(String),
+    Terminal(String),
+    Pipe,
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '-'
+}
+
+/// Read a quoted terminal whose opening quote is at `col`, with `\"`, `\\`, `\n` and `\t` escapes
+fn read_terminal(chars: &mut impl Iterator<Item = (usize, char)>, col: usize) -> Result<String> {
+    let mut terminal = String::new();
+    loop {
+        let c = match chars.next() {
+            Some((_, '"')) if terminal.is_empty() => {
+                return Err(anyhow!("Empty terminal at column {}", col + 1))
+            }
+            Some((_, '"')) => return Ok(terminal),
+            Some((_, '\\')) => match chars.next() {
+                Some((_, 'n')) => '\n',
+                Some((_, 't')) => '\t',
+                Some((_, c)) if c == '"' || c == '\\' => c,
+                Some((col, c)) => {
+                    return Err(anyhow!("Invalid escape '\\{}' at column {}", c, col + 1))
+                }
+                None => break,
+            },
+            Some((_, c)) => c,
+            None => break,
+        };
+        terminal.push(c);
+    }
+    Err(anyhow!("Unterminated terminal at column {}", col + 1))
+}
+
+/// Split a rule body starting at `offset` in its line into rule names, quoted terminals and `|`
+fn tokenize(body: &str, offset: usize) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = body.char_indices().map(|(i, c)| (i + offset, c)).peekable();
+    while let Some((col, c)) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '|' => tokens.push(Token::Pipe),
+            '"' => tokens.push(Token::Terminal(read_terminal(&mut chars, col)?)),
+            c if is_name_char(c) => {
+                let mut name = c.to_string();
+                while let Some((_, c)) = chars.next_if(|(_, c)| is_name_char(*c)) {
+                    name.push(c);
+                }
+                tokens.push(Token::Name(name));
+            }
+            c => return Err(anyhow!("Unexpected '{}' at column {}", c, col + 1)),
+        }
+    }
+    Ok(tokens)
+}
+
+impl Grammar {
+    fn intern(&mut self, name: &str) -> RuleId {
+        if let Some(&id) = self.ids.get(name) {
+            return id;
+        }
+        let id = self.rules.len();
+        self.rules.push(None);
+        self.names.push(name.to_string());
+        self.ids.insert(name.to_string(), id);
+        id
+    }
+
+    fn define(&mut self, id: RuleId, tokens: Vec<Token>) -> Result<()> {
+        if let [Token::Terminal(t)] = tokens.as_slice() {
+            self.rules[id] = Some(Rule::Terminal(t.clone()));
+            return Ok(());
+        }
+        let mut alts = vec![Vec::new()];
+        for token in tokens {
+            match token {
+                Token::Pipe => alts.push(Vec::new()),
+                Token::Name(name) => {
+                    let r = self.intern(&name);
+                    alts.last_mut().unwrap().push(r)
+                }
+                Token::Terminal(t) => {
+                    // Inline terminals become anonymous rules named after their quoted form
+                    let r = self.intern(&format!("{:?}", t));
+                    self.rules[r] = Some(Rule::Terminal(t));
+                    alts.last_mut().unwrap().push(r)
+                }
+            }
+        }
+        if alts.iter().any(|seq| seq.is_empty()) {
+            return Err(anyhow!("Empty alternative"));
+        }
+        self.rules[id] = Some(Rule::Alternatives(alts));
+        Ok(())
+    }
+}
+
+impl FromStr for Grammar {
+    type Err = anyhow::Error;
+
+    /// One `name: body` rule per line. Names are made of alphanumerics, `_` and `-`,
+    /// bodies are sequences of names and quoted terminals separated by `|`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut grammar = Grammar::default();
+        let mut bodies = Vec::new();
+        // Declare all the names first so that ids follow the definition order
+        for (line_idx, line) in s.lines().enumerate().filter(|(_, l)| !l.trim().is_empty()) {
+            let line_nb = line_idx + 1;
+            let mut split = line.splitn(2, ':');
+            let (name, body) = match (split.next(), split.next()) {
+                (Some(name), Some(body)) => (name, body),
+                _ => return Err(anyhow!("line {}: expected `name: rule`", line_nb)),
+            };
+            let name = name.trim();
+            if name.is_empty() || !name.chars().all(is_name_char) {
+                return Err(anyhow!("line {}: invalid rule name '{}'", line_nb, name));
+            }
+            if grammar.ids.contains_key(name) {
+                return Err(anyhow!("line {}: rule {} is defined twice", line_nb, name));
+            }
+            bodies.push((line_nb, grammar.intern(name), body, line.len() - body.len()));
+        }
+        if bodies.is_empty() {
+            return Err(anyhow!("No rules"));
+        }
+        for (line_nb, id, body, offset) in bodies {
+            tokenize(body, offset)
+                .and_then(|tokens| grammar.define(id, tokens))
+                .map_err(|e| anyhow!("line {}: {}", line_nb, e))?;
+        }
+        grammar.root = grammar.id("0").unwrap_or(0);
+        Ok(grammar)
+    }
+}
+
+/// Rule `rule` matched `span` of the message through `children`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseTree {
+    pub rule: RuleId,
+    pub span: Range<usize>,
+    pub children: Vec<ParseTree>,
+}
+
+/// Why a message was rejected: `message[..prefix_len]` is the longest prefix that could be
+/// matched, and `expected` lists the terminal rules that failed right after it.
+/// `expected` is empty when the whole grammar matched and only the end of input was expected.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rejection {
+    pub prefix_len: usize,
+    pub expected: Vec<RuleId>,
+}
+
+impl Grammar {
+    pub fn id(&self, name: &str) -> Option<RuleId> {
+        self.ids.get(name).copied()
+    }
+
+    pub fn name(&self, id: RuleId) -> &str {
+        &self.names[id]
+    }
+
+    /// Rule `0` if it exists, the first rule otherwise
+    pub fn root(&self) -> RuleId {
+        self.root
+    }
+
+    pub fn rule(&self, id: RuleId) -> Option<&Rule> {
+        self.rules.get(id)?.as_ref()
+    }
+
+    pub fn is_match(&self, message: &str) -> bool {
+        self.parse(message).is_ok()
+    }
+
+    /// Parse `message` from the root rule
+    pub fn parse(&self, message: &str) -> Result<ParseTree, Rejection> {
+        self.parse_from(self.root, message)
+    }
+
+    /// Parse `message` from `root`, returning the first derivation found
+    pub fn parse_from(&self, root: RuleId, message: &str) -> Result<ParseTree, Rejection> {
+        let mut matcher = Matcher::new(self, message);
+        let ends = matcher.ends(root, 0);
+        if ends.contains(&message.len()) {
+            let mut visiting = HashSet::new();
+            if let Some(tree) = matcher.tree(root, 0..message.len(), &mut visiting) {
+                return Ok(tree);
+            }
+        }
+        // The root matched a strict prefix: trailing input is unexpected
+        if let Some(&end) = ends.last() {
+            if end >= matcher.furthest {
+                return Err(Rejection {
+                    prefix_len: end,
+                    expected: Vec::new(),
+                });
+            }
+        }
+        Err(Rejection {
+            prefix_len: matcher.furthest,
+            expected: matcher.expected,
+        })
+    }
+}
+
+struct Matcher<'g, 'm> {
+    grammar: &'g Grammar,
+    message: &'m str,
+    // All the positions where a rule starting at a given position can end, as far as
+    // known: left recursive rules grow theirs over several passes
+    memo: HashMap<(RuleId, usize), Vec<usize>>,
+    // Rules being matched, and already matched, in the current pass
+    active: HashSet<(RuleId, usize)>,
+    done: HashSet<(RuleId, usize)>,
+    // Whether the current pass used an active rule, and grew an end set
+    recursed: bool,
+    grown: bool,
+    // Furthest failure and the terminals expected there
+    furthest: usize,
+    expected: Vec<RuleId>,
+}
+
+impl<'g, 'm> Matcher<'g, 'm> {
+    fn new(grammar: &'g Grammar, message: &'m str) -> Self {
+        Self {
+            grammar,
+            message,
+            memo: HashMap::new(),
+            active: HashSet::new(),
+            done: HashSet::new(),
+            recursed: false,
+            grown: false,
+            furthest: 0,
+            expected: Vec::new(),
+        }
+    }
+
+    fn fail(&mut self, pos: usize, id: RuleId) {
+        if pos > self.furthest {
+            self.furthest = pos;
+            self.expected.clear();
+        }
+        if pos == self.furthest && !self.expected.contains(&id) {
+            self.expected.push(id);
+        }
+    }
+
+    /// Sorted end positions of rule `id` matched from `start`.
+    /// A rule used again while it is being matched only has the ends known so far, which
+    /// stops left recursion. Those grow from none, so the matching is repeated until
+    /// no end set grows any more.
+    fn ends(&mut self, id: RuleId, start: usize) -> Vec<usize> {
+        if self.done.contains(&(id, start)) {
+            return self.memo[&(id, start)].clone();
+        }
+        loop {
+            self.done.clear();
+            self.recursed = false;
+            self.grown = false;
+            let ends = self.grow(id, start);
+            if !(self.recursed && self.grown) {
+                return ends;
+            }
+        }
+    }
+
+    /// One pass of `ends`, reusing the ends found earlier in the pass
+    fn grow(&mut self, id: RuleId, start: usize) -> Vec<usize> {
+        let key = (id, start);
+        if self.done.contains(&key) || self.active.contains(&key) {
+            self.recursed |= self.active.contains(&key);
+            return self.memo.get(&key).cloned().unwrap_or_default();
+        }
+        self.active.insert(key);
+
+        let ends = match self.grammar.rule(id) {
+            Some(Rule::Terminal(t)) if self.message[start..].starts_with(t.as_str()) => {
+                vec![start + t.len()]
+            }
+            Some(Rule::Alternatives(alts)) => {
+                let mut ends = Vec::new();
+                for seq in alts {
+                    ends.extend(self.seq_ends(seq, start));
+                }
+                ends.sort_unstable();
+                ends.dedup();
+                ends
+            }
+            Some(Rule::Terminal(_)) | None => {
+                self.fail(start, id);
+                Vec::new()
+            }
+        };
+        self.active.remove(&key);
+        self.done.insert(key);
+        if self.memo.insert(key, ends.clone()).as_ref() != Some(&ends) {
+            self.grown = true;
+        }
+        ends
+    }
+
+    fn seq_ends(&mut self, seq: &[RuleId], start: usize) -> Vec<usize> {
+        let mut positions = vec![start];
+        for &id in seq {
+            let mut next = Vec::new();
+            for pos in positions {
+                next.extend(self.grow(id, pos));
+            }
+            next.sort_unstable();
+            next.dedup();
+            positions = next;
+            if positions.is_empty() {
+                break;
+            }
+        }
+        positions
+    }
+
+    /// Rebuild a derivation of `id` over `span` from the memoized end positions
+    fn tree(
+        &mut self,
+        id: RuleId,
+        span: Range<usize>,
+        visiting: &mut HashSet<(RuleId, usize, usize)>,
+    ) -> Option<ParseTree> {
+        let rule = self.grammar.rule(id)?;
+        let key = (id, span.start, span.end);
+        if !visiting.insert(key) {
+            return None; // unit cycle
+        }
+        let children = match rule {
+            Rule::Terminal(_) => Some(Vec::new()),
+            Rule::Alternatives(alts) => alts
+                .iter()
+                .find_map(|seq| self.split(seq, span.clone(), visiting)),
+        };
+        visiting.remove(&key);
+        Some(ParseTree {
+            rule: id,
+            span,
+            children: children?,
+        })
+    }
+
+    fn split(
+        &mut self,
+        seq: &[RuleId],
+        span: Range<usize>,
+        visiting: &mut HashSet<(RuleId, usize, usize)>,
+    ) -> Option<Vec<ParseTree>> {
+        let (&id, rest) = match seq.split_first() {
+            Some(split) => split,
+            None if span.is_empty() => return Some(Vec::new()),
+            None => return None,
+        };
+        for mid in self.ends(id, span.start) {
+            if mid > span.end {
+                break;
+            }
+            if let Some(mut trees) = self.split(rest, mid..span.end, visiting) {
+                if let Some(tree) = self.tree(id, span.start..mid, visiting) {
+                    trees.insert(0, tree);
+                    return Some(trees);
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Indented display of a parse tree with the rule names of `grammar`
+pub struct TreeDisplay<'a> {
+    grammar: &'a Grammar,
+    tree: &'a ParseTree,
+}
+
+impl Grammar {
+    pub fn display_tree<'a>(&'a self, tree: &'a ParseTree) -> TreeDisplay<'a> {
+        TreeDisplay {
+            grammar: self,
+            tree,
+        }
+    }
+}
+
+impl TreeDisplay<'_> {
+    fn fmt_indent(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+        tree: &ParseTree,
+        depth: usize,
+    ) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{:indent$}{} [{}..{}]",
+            "",
+            self.grammar.name(tree.rule),
+            tree.span.start,
+            tree.span.end,
+            indent = 2 * depth
+        )?;
+        tree.children
+            .iter()
+            .try_for_each(|c| self.fmt_indent(f, c, depth + 1))
+    }
+}
+
+impl Display for TreeDisplay<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.fmt_indent(f, self.tree, 0)
+    }
+}
+
+// Grammar analysis
+
+/// Static analysis of a grammar from its root rule, rules are reported by name
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Analysis {
+    /// (rule, missing rule) for every reference to an undefined rule
+    pub undefined: Vec<(String, String)>,
+    /// Defined rules that the root never uses
+    pub unreachable: Vec<String>,
+    /// Rules that can derive themselves without consuming any input first
+    pub left_recursive: Vec<String>,
+    /// (rule, alternative, alternative) whose languages overlap.
+    /// Only checked for rules with a finite language of at most `LANGUAGE_LIMIT` strings.
+    pub ambiguous: Vec<(String, usize, usize)>,
+    /// No rule is self-embedding (`A => xAy` with non-empty `x` and `y`)
+    pub regular: bool,
+}
+
+impl Display for Analysis {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (rule, missing) in &self.undefined {
+            writeln!(f, "rule {} references undefined rule {}", rule, missing)?;
+        }
+        for rule in &self.unreachable {
+            writeln!(f, "rule {} is unreachable", rule)?;
+        }
+        for rule in &self.left_recursive {
+            writeln!(f, "rule {} is left recursive", rule)?;
+        }
+        for (rule, a, b) in &self.ambiguous {
+            writeln!(f, "rule {}: alternatives {} and {} overlap", rule, a, b)?;
+        }
+        writeln!(
+            f,
+            "language is {}",
+            if self.regular {
+                "regular"
+            } else {
+                "not regular"
+            }
+        )
+    }
+}
+
+/// Matches messages with a compiled regex when the language is regular
+pub enum Recognizer<'g> {
+    Regex(Regex),
+    General(&'g Grammar),
+}
+
+impl Recognizer<'_> {
+    pub fn is_match(&self, message: &str) -> bool {
+        match self {
+            Recognizer::Regex(re) => re.is_match(message),
+            Recognizer::General(grammar) => grammar.is_match(message),
+        }
+    }
+}
+
+const LANGUAGE_LIMIT: usize = 1 << 12;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Side {
+    Left,
+    Right,
+}
+
+impl Grammar {
+    fn ids(&self) -> impl Iterator<Item = RuleId> + '_ {
+        (0..self.rules.len()).filter(move |&id| self.rule(id).is_some())
+    }
+
+    fn references(&self, id: RuleId) -> impl Iterator<Item = RuleId> + '_ {
+        let alts = match self.rule(id) {
+            Some(Rule::Alternatives(alts)) => alts.as_slice(),
+            _ => &[],
+        };
+        alts.iter().flatten().copied()
+    }
+
+    /// Rules reachable from `id` in one or more steps
+    fn reachable(&self, id: RuleId) -> HashSet<RuleId> {
+        let mut seen = HashSet::new();
+        let mut stack: Vec<_> = self.references(id).collect();
+        while let Some(next) = stack.pop() {
+            if seen.insert(next) {
+                stack.extend(self.references(next));
+            }
+        }
+        seen
+    }
+
+    fn nullable(&self) -> HashSet<RuleId> {
+        let mut nullable = HashSet::new();
+        loop {
+            let new: Vec<_> = self
+                .ids()
+                .filter(|id| !nullable.contains(id))
+                .filter(|&id| match self.rule(id) {
+                    Some(Rule::Alternatives(alts)) => alts
+                        .iter()
+                        .any(|seq| seq.iter().all(|r| nullable.contains(r))),
+                    _ => false,
+                })
+                .collect();
+            if new.is_empty() {
+                return nullable;
+            }
+            nullable.extend(new);
+        }
+    }
+
+    fn left_recursive(&self, nullable: &HashSet<RuleId>) -> Vec<RuleId> {
+        // Rules that can start a derivation of `id`
+        let left_corners = |id| -> Vec<RuleId> {
+            let mut corners = Vec::new();
+            if let Some(Rule::Alternatives(alts)) = self.rule(id) {
+                for seq in alts {
+                    for r in seq {
+                        corners.push(*r);
+                        if !nullable.contains(r) {
+                            break;
+                        }
+                    }
+                }
+            }
+            corners
+        };
+        self.ids()
+            .filter(|&id| {
+                let mut seen = HashSet::new();
+                let mut stack = left_corners(id);
+                while let Some(next) = stack.pop() {
+                    if next == id {
+                        return true;
+                    }
+                    if seen.insert(next) {
+                        stack.extend(left_corners(next));
+                    }
+                }
+                false
+            })
+            .collect()
+    }
+
+    /// Finite language of a sequence, `None` if it is infinite or too large
+    fn seq_language(
+        &self,
+        seq: &[RuleId],
+        memo: &mut HashMap<RuleId, Option<HashSet<String>>>,
+    ) -> Option<HashSet<String>> {
+        seq.iter().try_fold(
+            std::iter::once(String::new()).collect::<HashSet<_>>(),
+            |acc, &r| {
+                let lang = self.language(r, memo)?;
+                if acc.len() * lang.len() > LANGUAGE_LIMIT {
+                    return None;
+                }
+                Some(
+                    acc.iter()
+                        .flat_map(|a| lang.iter().map(move |b| format!("{}{}", a, b)))
+                        .collect(),
+                )
+            },
+        )
+    }
+
+    fn language(
+        &self,
+        id: RuleId,
+        memo: &mut HashMap<RuleId, Option<HashSet<String>>>,
+    ) -> Option<HashSet<String>> {
+        if let Some(lang) = memo.get(&id) {
+            return lang.clone();
+        }
+        memo.insert(id, None); // recursive rules have an infinite language
+        let lang = match self.rule(id)? {
+            Rule::Terminal(t) => Some(std::iter::once(t.clone()).collect()),
+            Rule::Alternatives(alts) => alts.iter().try_fold(HashSet::new(), |mut acc, seq| {
+                acc.extend(self.seq_language(seq, memo)?);
+                Some(acc).filter(|acc| acc.len() <= LANGUAGE_LIMIT)
+            }),
+        };
+        memo.insert(id, lang.clone());
+        lang
+    }
+
+    fn ambiguous(&self) -> Vec<(RuleId, usize, usize)> {
+        let mut memo = HashMap::new();
+        let mut ambiguous = Vec::new();
+        for id in self.ids() {
+            let alts = match self.rule(id) {
+                Some(Rule::Alternatives(alts)) if alts.len() > 1 => alts,
+                _ => continue,
+            };
+            let langs: Vec<_> = alts
+                .iter()
+                .map(|seq| self.seq_language(seq, &mut memo))
+                .collect();
+            for i in 0..alts.len() {
+                for j in i + 1..alts.len() {
+                    let overlap = match (&langs[i], &langs[j]) {
+                        (Some(a), Some(b)) => !a.is_disjoint(b),
+                        _ => alts[i] == alts[j],
+                    };
+                    if overlap {
+                        ambiguous.push((id, i, j));
+                    }
+                }
+            }
+        }
+        ambiguous
+    }
+
+    /// Side of the recursion for the strongly connected component `scc`,
+    /// or `None` if one of its rules is self-embedding
+    fn recursion_side(&self, scc: &HashSet<RuleId>) -> Option<Side> {
+        let mut side = None;
+        for &id in scc {
+            for seq in self.alternatives(id) {
+                let mut recursive = seq.iter().enumerate().filter(|(_, r)| scc.contains(*r));
+                let pos = match (recursive.next(), recursive.next()) {
+                    (None, _) => continue,
+                    (Some((pos, _)), None) => pos,
+                    (Some(_), Some(_)) => return None,
+                };
+                let seq_side = match (pos == 0, pos == seq.len() - 1) {
+                    (true, true) => continue, // unit rule
+                    (true, false) => Side::Left,
+                    (false, true) => Side::Right,
+                    (false, false) => return None,
+                };
+                if *side.get_or_insert(seq_side) != seq_side {
+                    return None;
+                }
+            }
+        }
+        Some(side.unwrap_or(Side::Right))
+    }
+
+    fn alternatives(&self, id: RuleId) -> &[Vec<RuleId>] {
+        match self.rule(id) {
+            Some(Rule::Alternatives(alts)) => alts,
+            _ => &[],
+        }
+    }
+
+    /// (rule, missing rule) for every reference to an undefined rule
+    fn undefined(&self) -> Vec<(RuleId, RuleId)> {
+        self.ids()
+            .flat_map(|id| {
+                self.references(id)
+                    .filter(move |&r| self.rule(r).is_none())
+                    .map(move |r| (id, r))
+            })
+            .unique()
+            .collect()
+    }
+
+    pub fn analyse(&self) -> Analysis {
+        let name = |id| self.name(id).to_string();
+        let reachable = self.reachable(self.root);
+        let undefined = self
+            .undefined()
+            .into_iter()
+            .map(|(id, r)| (name(id), name(r)))
+            .collect();
+        let unreachable = self
+            .ids()
+            .filter(|&id| id != self.root && !reachable.contains(&id))
+            .map(name)
+            .collect();
+        let regular = self
+            .sccs()
+            .iter()
+            .all(|scc| self.recursion_side(scc).is_some());
+        Analysis {
+            undefined,
+            unreachable,
+            left_recursive: self
+                .left_recursive(&self.nullable())
+                .into_iter()
+                .map(name)
+                .collect(),
+            ambiguous: self
+                .ambiguous()
+                .into_iter()
+                .map(|(id, i, j)| (name(id), i, j))
+                .collect(),
+            regular,
+        }
+    }
+
+    /// Recursive strongly connected components of the rule graph
+    fn sccs(&self) -> Vec<HashSet<RuleId>> {
+        let reach: HashMap<RuleId, HashSet<RuleId>> =
+            self.ids().map(|id| (id, self.reachable(id))).collect();
+        let mut sccs: Vec<HashSet<RuleId>> = Vec::new();
+        for id in self.ids().filter(|id| reach[id].contains(id)) {
+            if sccs.iter().any(|scc| scc.contains(&id)) {
+                continue;
+            }
+            sccs.push(
+                reach[&id]
+                    .iter()
+                    .copied()
+                    .filter(|r| reach.get(r).map_or(false, |rr| rr.contains(&id)))
+                    .collect(),
+            );
+        }
+        sccs
+    }
+
+    /// Compile the language of the root rule into a single regex if it is regular
+    pub fn to_regex(&self) -> Option<Regex> {
+        let sccs = self.sccs();
+        let regular = sccs.iter().all(|scc| self.recursion_side(scc).is_some());
+        let reachable = self.reachable(self.root);
+        let undefined = self
+            .undefined()
+            .iter()
+            .any(|(r, _)| *r == self.root || reachable.contains(r));
+        if !regular || undefined {
+            return None;
+        }
+        let mut compiler = RegexCompiler {
+            grammar: self,
+            sccs,
+            memo: HashMap::new(),
+        };
+        let re = compiler.compile(self.root);
+        Regex::new(&format!("^{}$", re)).ok()
+    }
+
+    /// Regex when the language is regular, general matcher otherwise
+    pub fn recognizer(&self) -> Recognizer<'_> {
+        match self.to_regex() {
+            Some(re) => Recognizer::Regex(re),
+            None => Recognizer::General(self),
+        }
+    }
+}
+
+struct RegexCompiler<'g> {
+    grammar: &'g Grammar,
+    sccs: Vec<HashSet<RuleId>>,
+    memo: HashMap<RuleId, String>,
+}
+
+// Never matches
+const EMPTY_LANGUAGE: &str = r"\b\B";
+
+fn alt_regex(mut alts: Vec<String>) -> String {
+    alts.sort();
+    alts.dedup();
+    match alts.len() {
+        0 => EMPTY_LANGUAGE.to_string(),
+        1 => alts.remove(0),
+        _ => format!("(?:{})", alts.join("|")),
+    }
+}
+
+fn star_regex(re: &str) -> String {
+    if re.is_empty() {
+        String::new()
+    } else {
+        format!("(?:{})*", re)
+    }
+}
+
+impl RegexCompiler<'_> {
+    fn compile(&mut self, id: RuleId) -> String {
+        if let Some(re) = self.memo.get(&id) {
+            return re.clone();
+        }
+        match self.sccs.iter().position(|scc| scc.contains(&id)) {
+            Some(scc) => self.compile_scc(scc),
+            None => {
+                let re = match self.grammar.rule(id) {
+                    Some(Rule::Terminal(t)) => regex::escape(t),
+                    Some(Rule::Alternatives(alts)) => alt_regex(
+                        alts.iter()
+                            .map(|seq| seq.iter().map(|&r| self.compile(r)).collect())
+                            .collect(),
+                    ),
+                    None => EMPTY_LANGUAGE.to_string(),
+                };
+                self.memo.insert(id, re);
+            }
+        }
+        self.memo[&id].clone()
+    }
+
+    /// Solve the (left or right) linear equations of a recursive component with Arden's lemma:
+    /// `A = xA | y` is `A = x*y` (and `A = Ax | y` is `A = yx*`).
+    fn compile_scc(&mut self, scc: usize) {
+        let grammar = self.grammar;
+        let mut members: Vec<RuleId> = self.sccs[scc].iter().copied().collect();
+        members.sort_unstable();
+        let side = grammar
+            .recursion_side(&self.sccs[scc])
+            .expect("Regular grammar");
+        let var = |r: RuleId| members.iter().position(|m| *m == r);
+        let concat = |side: Side, coef: &str, rest: &str| match side {
+            Side::Right => format!("{}{}", coef, rest),
+            Side::Left => format!("{}{}", rest, coef),
+        };
+
+        // equations[i][Some(j)] is the coefficient of member j in member i, [None] the constant
+        let mut equations: Vec<BTreeMap<Option<usize>, Vec<String>>> = Vec::new();
+        for &id in &members {
+            let mut eq: BTreeMap<_, Vec<_>> = BTreeMap::new();
+            for seq in grammar.alternatives(id) {
+                let v = seq.iter().find_map(|&r| var(r));
+                let rest: String = seq
+                    .iter()
+                    .filter(|&&r| var(r).is_none())
+                    .map(|&r| self.compile(r))
+                    .collect();
+                eq.entry(v).or_default().push(rest);
+            }
+            equations.push(eq);
+        }
+
+        for k in 0..members.len() {
+            if let Some(coefs) = equations[k].remove(&Some(k)) {
+                let star = star_regex(&alt_regex(coefs));
+                for coefs in equations[k].values_mut() {
+                    *coefs = vec![concat(side, &star, &alt_regex(coefs.clone()))];
+                }
+            }
+            let eq_k = equations[k].clone();
+            for (_, eq) in equations.iter_mut().enumerate().filter(|(i, _)| *i != k) {
+                if let Some(coefs) = eq.remove(&Some(k)) {
+                    let coef = alt_regex(coefs);
+                    for (v, c) in &eq_k {
+                        let c = concat(side, &coef, &alt_regex(c.clone()));
+                        eq.entry(*v).or_default().push(c);
+                    }
+                }
+            }
+        }
+
+        for (id, mut eq) in members.iter().zip(equations) {
+            let re = alt_regex(eq.remove(&None).unwrap_or_default());
+            self.memo.insert(*id, re);
+        }
+    }
+}
+
+// Message generation
+
+/// A message of the grammar's language and, if requested, the derivation that produced it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Generated {
+    pub message: String,
+    pub derivation: Option<ParseTree>,
+}
+
+/// Enumerates and samples the language of a grammar by length (in bytes).
+/// Derivations going around a cycle of unit rules (`1: 2`, `2: 1`) are not generated.
+pub struct Generator<'g> {
+    grammar: &'g Grammar,
+    // Number of derivations of each rule for a given length
+    counts: HashMap<(RuleId, usize), u128>,
+    derivations: HashMap<(RuleId, usize), Vec<(String, ParseTree)>>,
+}
+
+fn shift(mut tree: ParseTree, offset: usize) -> ParseTree {
+    tree.span = tree.span.start + offset..tree.span.end + offset;
+    tree.children = tree
+        .children
+        .into_iter()
+        .map(|c| shift(c, offset))
+        .collect();
+    tree
+}
+
+impl Grammar {
+    pub fn generator(&self) -> Generator<'_> {
+        Generator {
+            grammar: self,
+            counts: HashMap::new(),
+            derivations: HashMap::new(),
+        }
+    }
+
+    /// Characters used by the terminals
+    fn alphabet(&self) -> Vec<char> {
+        self.ids()
+            .filter_map(|id| match self.rule(id) {
+                Some(Rule::Terminal(t)) => Some(t.chars()),
+                _ => None,
+            })
+            .flatten()
+            .sorted()
+            .dedup()
+            .collect()
+    }
+}
+
+impl Generator<'_> {
+    /// Number of derivations of `id` of exactly `len` bytes, saturating at `u128::MAX`
+    fn count(&mut self, id: RuleId, len: usize) -> u128 {
+        if let Some(&count) = self.counts.get(&(id, len)) {
+            return count;
+        }
+        let count = self.chain_count(id, len, &mut Vec::new());
+        self.counts.insert((id, len), count);
+        count
+    }
+
+    /// Number of derivations of `id` of `len` bytes reached through the unit rules of
+    /// `chain`, which don't go through these rules again. They depend on `chain`, so
+    /// only `count` memoizes them, with an empty one.
+    fn chain_count(&mut self, id: RuleId, len: usize, chain: &mut Vec<RuleId>) -> u128 {
+        match self.grammar.rule(id) {
+            Some(Rule::Terminal(t)) => (t.len() == len) as u128,
+            Some(Rule::Alternatives(alts)) => {
+                chain.push(id);
+                let count = alts.iter().fold(0u128, |acc, seq| {
+                    let count = match seq.as_slice() {
+                        [unit] if chain.contains(unit) => 0,
+                        [unit] => self.chain_count(*unit, len, chain),
+                        _ => self.seq_count(seq, len),
+                    };
+                    acc.saturating_add(count)
+                });
+                chain.pop();
+                count
+            }
+            None => 0,
+        }
+    }
+
+    /// Every rule derives at least one byte, so the rules of a sequence derive fewer
+    /// bytes than the whole of it
+    fn seq_count(&mut self, seq: &[RuleId], len: usize) -> u128 {
+        match seq {
+            [] => (len == 0) as u128,
+            [id] => self.count(*id, len),
+            [id, rest @ ..] => (1..=len.saturating_sub(rest.len())).fold(0u128, |acc, l| {
+                let head = self.count(*id, l);
+                if head == 0 {
+                    return acc;
+                }
+                acc.saturating_add(head.saturating_mul(self.seq_count(rest, len - l)))
+            }),
+        }
+    }
+
+    /// Number of derivations of the root of at most `max_len` bytes
+    pub fn count_up_to(&mut self, max_len: usize) -> u128 {
+        let root = self.grammar.root();
+        (1..=max_len).fold(0u128, |acc, len| acc.saturating_add(self.count(root, len)))
+    }
+
+    /// All derivations of `id` of exactly `len` bytes, with spans starting at 0
+    fn derive(&mut self, id: RuleId, len: usize) -> Vec<(String, ParseTree)> {
+        if self.count(id, len) == 0 {
+            return Vec::new();
+        }
+        if let Some(derivations) = self.derivations.get(&(id, len)) {
+            return derivations.clone();
+        }
+        let derivations = self.chain_derive(id, len, &mut Vec::new());
+        self.derivations.insert((id, len), derivations.clone());
+        derivations
+    }
+
+    /// Derivations counted by `chain_count`
+    fn chain_derive(
+        &mut self,
+        id: RuleId,
+        len: usize,
+        chain: &mut Vec<RuleId>,
+    ) -> Vec<(String, ParseTree)> {
+        let derivations = match self.grammar.rule(id) {
+            Some(Rule::Terminal(t)) if t.len() == len => vec![(t.clone(), Vec::new())],
+            Some(Rule::Alternatives(alts)) => {
+                chain.push(id);
+                let mut derivations = Vec::new();
+                for seq in alts {
+                    match seq.as_slice() {
+                        [unit] if chain.contains(unit) => {}
+                        [unit] => derivations.extend(
+                            self.chain_derive(*unit, len, chain)
+                                .into_iter()
+                                .map(|(message, tree)| (message, vec![tree])),
+                        ),
+                        _ => derivations.extend(self.derive_seq(seq, len)),
+                    }
+                }
+                chain.pop();
+                derivations
+            }
+            _ => Vec::new(),
+        };
+        derivations
+            .into_iter()
+            .map(|(message, children)| {
+                let tree = ParseTree {
+                    rule: id,
+                    span: 0..len,
+                    children,
+                };
+                (message, tree)
+            })
+            .collect()
+    }
+
+    fn derive_seq(&mut self, seq: &[RuleId], len: usize) -> Vec<(String, Vec<ParseTree>)> {
+        let (&id, rest) = match seq.split_first() {
+            Some(split) => split,
+            None if len == 0 => return vec![(String::new(), Vec::new())],
+            None => return Vec::new(),
+        };
+        let mut out = Vec::new();
+        for l in 1..=len.saturating_sub(rest.len()) {
+            if self.count(id, l) == 0 || self.seq_count(rest, len - l) == 0 {
+                continue;
+            }
+            let tails = self.derive_seq(rest, len - l);
+            for (head, tree) in self.derive(id, l) {
+                for (tail, trees) in &tails {
+                    let mut children = vec![tree.clone()];
+                    children.extend(trees.iter().cloned().map(|t| shift(t, l)));
+                    out.push((format!("{}{}", head, tail), children));
+                }
+            }
+        }
+        out
+    }
+
+    /// Every message of at most `max_len` bytes, by length then alphabetical order.
+    /// With `derivations`, ambiguous messages are listed once per derivation.
+    pub fn enumerate(&mut self, max_len: usize, derivations: bool) -> Vec<Generated> {
+        let root = self.grammar.root();
+        let mut generated: Vec<Generated> = (1..=max_len)
+            .flat_map(|len| self.derive(root, len))
+            .map(|(message, tree)| Generated {
+                message,
+                derivation: Some(tree).filter(|_| derivations),
+            })
+            .collect();
+        generated.sort_by(|a, b| (a.message.len(), &a.message).cmp(&(b.message.len(), &b.message)));
+        if !derivations {
+            generated.dedup();
+        }
+        generated
+    }
 
-    messages.iter().filter(|msg| re.is_match(*msg)).count()
+    /// One derivation of `id` of exactly `len` bytes, uniformly among those counted by
+    /// `chain_count`
+    fn sample_rule(
+        &mut self,
+        id: RuleId,
+        len: usize,
+        chain: &mut Vec<RuleId>,
+        rng: &mut Rng,
+    ) -> (String, ParseTree) {
+        let (message, children) = match self.grammar.rule(id) {
+            Some(Rule::Terminal(t)) => (t.clone(), Vec::new()),
+            Some(Rule::Alternatives(alts)) => {
+                chain.push(id);
+                let weights: Vec<_> = alts
+                    .iter()
+                    .map(|seq| match seq.as_slice() {
+                        [unit] if chain.contains(unit) => 0,
+                        [unit] => self.chain_count(*unit, len, chain),
+                        _ => self.seq_count(seq, len),
+                    })
+                    .collect();
+                let seq = &alts[pick(&weights, rng)];
+                let sampled = match seq.as_slice() {
+                    [unit] => {
+                        let (message, tree) = self.sample_rule(*unit, len, chain, rng);
+                        (message, vec![tree])
+                    }
+                    _ => self.sample_seq(seq, len, rng),
+                };
+                chain.pop();
+                sampled
+            }
+            None => unreachable!("Undefined rules have no derivation"),
+        };
+        let tree = ParseTree {
+            rule: id,
+            span: 0..len,
+            children,
+        };
+        (message, tree)
+    }
+
+    fn sample_seq(
+        &mut self,
+        seq: &[RuleId],
+        len: usize,
+        rng: &mut Rng,
+    ) -> (String, Vec<ParseTree>) {
+        let (&id, rest) = match seq.split_first() {
+            Some(split) => split,
+            None => return (String::new(), Vec::new()),
+        };
+        let weights: Vec<_> = (0..=len.saturating_sub(rest.len()))
+            .map(|l| match l {
+                0 => 0,
+                l => self
+                    .count(id, l)
+                    .saturating_mul(self.seq_count(rest, len - l)),
+            })
+            .collect();
+        let l = pick(&weights, rng);
+        let (head, tree) = self.sample_rule(id, l, &mut Vec::new(), rng);
+        let (tail, trees) = self.sample_seq(rest, len - l, rng);
+        let mut children = vec![tree];
+        children.extend(trees.into_iter().map(|t| shift(t, l)));
+        (head + &tail, children)
+    }
+
+    /// Number of derivations of the root for each length up to `max_len`
+    fn length_weights(&mut self, max_len: usize) -> Vec<u128> {
+        let root = self.grammar.root();
+        (0..=max_len)
+            .map(|len| if len == 0 { 0 } else { self.count(root, len) })
+            .collect()
+    }
+
+    /// `n` messages of at most `max_len` bytes drawn uniformly among the derivations
+    /// (uniformly among messages when the grammar is unambiguous). Empty if there are none.
+    pub fn sample(
+        &mut self,
+        max_len: usize,
+        n: usize,
+        seed: u64,
+        derivations: bool,
+    ) -> Vec<Generated> {
+        let weights = self.length_weights(max_len);
+        if weights.iter().all(|w| *w == 0) {
+            return Vec::new();
+        }
+        let root = self.grammar.root();
+        let mut rng = Rng::new(seed);
+        (0..n)
+            .map(|_| {
+                let len = pick(&weights, &mut rng);
+                let (message, tree) = self.sample_rule(root, len, &mut Vec::new(), &mut rng);
+                Generated {
+                    message,
+                    derivation: Some(tree).filter(|_| derivations),
+                }
+            })
+            .collect()
+    }
+
+    /// Up to `n` distinct messages rejected by the grammar, each one edit away
+    /// (substitution, insertion or deletion of a character) from a sampled message
+    pub fn near_misses(&mut self, max_len: usize, n: usize, seed: u64) -> Vec<String> {
+        let weights = self.length_weights(max_len);
+        if weights.iter().all(|w| *w == 0) {
+            return Vec::new();
+        }
+        let root = self.grammar.root();
+        let recognizer = self.grammar.recognizer();
+        let alphabet = self.grammar.alphabet();
+        let mut rng = Rng::new(seed);
+        let mut misses = Vec::new();
+        // Bounded number of attempts for grammars with few near misses
+        for _ in 0..n * 16 {
+            if misses.len() == n {
+                break;
+            }
+            let len = pick(&weights, &mut rng);
+            let (message, _) = self.sample_rule(root, len, &mut Vec::new(), &mut rng);
+            let mut chars: Vec<char> = message.chars().collect();
+            let pos = rng.below(chars.len() as u128 + 1) as usize;
+            let c = alphabet[rng.below(alphabet.len() as u128) as usize];
+            match rng.below(3) {
+                0 if pos < chars.len() => chars[pos] = c,
+                1 if pos < chars.len() => {
+                    chars.remove(pos);
+                }
+                _ => chars.insert(pos, c),
+            }
+            let miss: String = chars.into_iter().collect();
+            if !recognizer.is_match(&miss) && !misses.contains(&miss) {
+                misses.push(miss);
+            }
+        }
+        misses
+    }
+}
+
+/// Index picked with a probability proportional to its weight. Weights are halved
+/// as long as their total overflows, which drops those too small to matter. Counts of
+/// derivations saturate at `u128::MAX` though: past that, picks are not proportional.
+fn pick(weights: &[u128], rng: &mut Rng) -> usize {
+    let mut shift = 0;
+    let total = loop {
+        match weights
+            .iter()
+            .try_fold(0u128, |acc, w| acc.checked_add(w >> shift))
+        {
+            Some(total) => break total,
+            None => shift += 1,
+        }
+    };
+    let mut x = rng.below(total);
+    for (idx, w) in weights.iter().map(|w| w >> shift).enumerate() {
+        if x < w {
+            return idx;
+        }
+        x -= w;
+    }
+    unreachable!("Weights sum to total")
 }
 
 #[cfg(test)]
@@ -152,4 +1389,255 @@ aabbbbbaabbbaaaaaabbbbbababaaaaabbaaabba";
     fn test_part2() {
         assert_eq!(part2(TESTCASE_PART_2), 12)
     }
+
+    #[test]
+    fn test_parse_tree() {
+        let input = input_parser(TESTCASE).unwrap();
+        let grammar = input.grammar();
+        let tree = grammar.parse("ababbb").unwrap();
+        assert_eq!(grammar.name(tree.rule), "0");
+        assert_eq!(tree.span, 0..6);
+        let rules: Vec<_> = tree
+            .children
+            .iter()
+            .map(|c| (grammar.name(c.rule), c.span.clone()))
+            .collect();
+        assert_eq!(rules, vec![("4", 0..1), ("1", 1..5), ("5", 5..6)]);
+        assert!(grammar.parse("abbbab").is_ok());
+    }
+
+    #[test]
+    fn test_rejection() {
+        let input = input_parser(TESTCASE).unwrap();
+        let grammar = input.grammar();
+        // "b" fails rule 0's leading "a"
+        assert_eq!(
+            grammar.parse("bababa"),
+            Err(Rejection {
+                prefix_len: 0,
+                expected: vec![grammar.id("4").unwrap()]
+            })
+        );
+        // Full match followed by an extra character
+        assert_eq!(
+            grammar.parse("aaaabbb"),
+            Err(Rejection {
+                prefix_len: 6,
+                expected: vec![]
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_loops() {
+        let input = TESTCASE_PART_2
+            .replace("8: 42", "8: 42 | 42 8")
+            .replace("11: 42 31", "11: 42 31 | 42 11 31");
+        let input = input_parser(&input).unwrap();
+        let grammar = input.grammar();
+        let count = input
+            .messages()
+            .iter()
+            .filter(|msg| grammar.is_match(msg))
+            .count();
+        assert_eq!(count, 12);
+
+        // The same loops written left recursive
+        let input = TESTCASE_PART_2
+            .replace("8: 42", "8: 8 42 | 42")
+            .replace("11: 42 31", "11: 42 31 | 42 11 31");
+        let input = input_parser(&input).unwrap();
+        let grammar = input.grammar();
+        let count = input
+            .messages()
+            .iter()
+            .filter(|msg| grammar.is_match(msg))
+            .count();
+        assert_eq!(count, 12);
+
+        let left: Grammar = "0: 0 1 | 1\n1: \"a\"".parse().unwrap();
+        let tree = left.parse("aaa").unwrap();
+        assert_eq!(tree.children[0].span, 0..2);
+        assert_eq!(tree.children[0].children[0].span, 0..1);
+        assert!(!left.is_match(""));
+        assert!(!left.is_match("aab"));
+    }
+
+    #[test]
+    fn test_analyse() {
+        let grammar: Grammar = "\
+0: 1 2 | 3
+1: \"a\"
+2: 4 | 1 2
+3: 1 4 | 1 9
+4: \"b\"
+5: 7
+6: \"c\"
+9: \"b\""
+            .parse()
+            .unwrap();
+        let analysis = grammar.analyse();
+        let names = |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert_eq!(analysis.undefined, vec![("5".to_string(), "7".to_string())]);
+        assert_eq!(analysis.unreachable, names(&["5", "6"]));
+        assert_eq!(analysis.left_recursive, names(&[]));
+        assert_eq!(analysis.ambiguous, vec![("3".to_string(), 0, 1)]);
+        assert!(analysis.regular);
+        // 0 = a a* b | a b
+        let re = grammar.to_regex().unwrap();
+        assert!(re.is_match("ab") && re.is_match("aaab") && !re.is_match("aaa"));
+
+        let left: Grammar = "0: 0 1 | 1\n1: \"a\"".parse().unwrap();
+        assert_eq!(left.analyse().left_recursive, names(&["0"]));
+        assert!(matches!(left.recognizer(), Recognizer::Regex(_)));
+        assert!(left.recognizer().is_match("aaa"));
+    }
+
+    #[test]
+    fn test_analyse_loops() {
+        let input = TESTCASE_PART_2
+            .replace("8: 42", "8: 42 | 42 8")
+            .replace("11: 42 31", "11: 42 31 | 42 11 31");
+        let input = input_parser(&input).unwrap();
+        let grammar = input.grammar();
+        assert!(!grammar.analyse().regular);
+        assert!(matches!(grammar.recognizer(), Recognizer::General(_)));
+
+        let input = TESTCASE_PART_2.replace("8: 42", "8: 42 | 42 8");
+        let input = input_parser(&input).unwrap();
+        assert!(input.grammar().analyse().regular);
+        assert!(matches!(input.grammar().recognizer(), Recognizer::Regex(_)));
+    }
+
+    #[test]
+    fn test_rule_file() {
+        let input = input_parser(
+            "\
+start: greeting \" \" 1000000 | \"(\" start \")\" | 7
+greeting: \"hello\" | \"hi\" | \"hey\" | \"yo\"
+1000000: \"wor.d\" | \"\\\"quoted\\\"\"
+7: \"a|b\"
+
+hello wor.d
+(hey \"quoted\")
+a|b
+hello world",
+        )
+        .unwrap();
+        let grammar = input.grammar();
+        assert_eq!(grammar.name(grammar.root()), "start");
+        let matches: Vec<_> = input
+            .messages()
+            .iter()
+            .map(|msg| grammar.is_match(msg))
+            .collect();
+        assert_eq!(matches, vec![true, true, true, false]);
+        // Terminals are escaped in the regex
+        let re = Grammar::from_str("0: \"wor.d\"")
+            .unwrap()
+            .to_regex()
+            .unwrap();
+        assert!(re.is_match("wor.d") && !re.is_match("world"));
+    }
+
+    #[test]
+    fn test_rule_file_errors() {
+        let err = |s: &str| Grammar::from_str(s).unwrap_err().to_string();
+        assert_eq!(err("0: 1\n1 \"a\""), "line 2: expected `name: rule`");
+        assert_eq!(err("0: 1\n0: 2"), "line 2: rule 0 is defined twice");
+        assert_eq!(err("0: 1 |\n1: \"a\""), "line 1: Empty alternative");
+        assert_eq!(err("0: \"a"), "line 1: Unterminated terminal at column 4");
+        assert_eq!(err("0: 1 \"\""), "line 1: Empty terminal at column 6");
+        assert_eq!(err("0: 1 + 2"), "line 1: Unexpected '+' at column 6");
+        assert!(input_parser("").is_err());
+    }
+
+    #[test]
+    fn test_enumerate() {
+        let input = input_parser(TESTCASE).unwrap();
+        let mut generator = input.grammar().generator();
+        let messages: Vec<_> = generator
+            .enumerate(6, false)
+            .into_iter()
+            .map(|g| g.message)
+            .collect();
+        assert_eq!(generator.count_up_to(6), 8);
+        assert_eq!(messages.len(), 8);
+        assert!(messages.contains(&"ababbb".to_string()));
+        assert!(messages.iter().all(|m| input.grammar().is_match(m)));
+
+        let with_tree = generator.enumerate(6, true);
+        let first = &with_tree[0];
+        let tree = first.derivation.as_ref().unwrap();
+        assert_eq!(input.grammar().parse(&first.message).as_ref(), Ok(tree));
+    }
+
+    #[test]
+    fn test_unit_cycles() {
+        // Unit rules go around the cycle at most once, whichever rule is counted first
+        let grammar = Grammar::from_str("0: 2 1\n1: 2\n2: 1 | \"a\"").unwrap();
+        let (one, two) = (grammar.id("1").unwrap(), grammar.id("2").unwrap());
+        let mut generator = grammar.generator();
+        assert_eq!((generator.count(two, 1), generator.count(one, 1)), (1, 1));
+        let mut other = grammar.generator();
+        assert_eq!((other.count(one, 1), other.count(two, 1)), (1, 1));
+        assert_eq!(generator.count_up_to(4), 1);
+        let messages: Vec<_> = generator
+            .enumerate(4, true)
+            .into_iter()
+            .map(|g| g.message)
+            .collect();
+        assert_eq!(messages, vec!["aa"]);
+        let samples = generator.sample(4, 10, 1, false);
+        assert!(samples.iter().all(|g| g.message == "aa"));
+
+        // "a" through 1, 1 then 2, 2 and 2 then 1, each picked about as often
+        let grammar = Grammar::from_str("0: 1 | 2\n1: 2 | \"a\"\n2: 1 | \"a\"").unwrap();
+        let mut generator = grammar.generator();
+        assert_eq!(generator.count_up_to(1), 4);
+        let derivations = generator.enumerate(1, true);
+        assert_eq!(derivations.len(), 4);
+        let picked = |samples: &[Generated], g| samples.iter().filter(|s| *s == g).count();
+        assert!(derivations.iter().all(|g| picked(&derivations, g) == 1));
+        let samples = generator.sample(1, 400, 5, true);
+        for g in &derivations {
+            let picked = samples.iter().filter(|s| *s == g).count();
+            assert!(
+                (60..140).contains(&picked),
+                "{:?} picked {} times",
+                g,
+                picked
+            );
+        }
+    }
+
+    #[test]
+    fn test_sample() {
+        let input = TESTCASE_PART_2
+            .replace("8: 42", "8: 42 | 42 8")
+            .replace("11: 42 31", "11: 42 31 | 42 11 31");
+        let input = input_parser(&input).unwrap();
+        let grammar = input.grammar();
+        let mut generator = grammar.generator();
+        let samples = generator.sample(40, 20, 42, true);
+        assert_eq!(samples.len(), 20);
+        for s in &samples {
+            assert!(s.message.len() <= 40);
+            assert!(grammar.is_match(&s.message));
+            assert_eq!(s.derivation.as_ref().unwrap().span, 0..s.message.len());
+        }
+        assert_eq!(samples, generator.sample(40, 20, 42, true));
+
+        let misses = generator.near_misses(40, 20, 7);
+        assert_eq!(misses.len(), 20);
+        assert!(misses.iter().all(|m| !grammar.is_match(m)));
+
+        // Huge weights whose total overflows are still picked evenly
+        let mut rng = Rng::new(3);
+        let picks: Vec<_> = (0..100)
+            .map(|_| pick(&[u128::MAX, 0, u128::MAX], &mut rng))
+            .collect();
+        assert!(!picks.contains(&1));
+        assert!((30..70).contains(&picks.iter().filter(|p| **p == 0).count()));
+    }
 }
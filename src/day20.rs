@@ -1,361 +1,1684 @@
+use anyhow::{anyhow, Result};
 use aoc_runner_derive::{aoc, aoc_generator};
 
-use hashbrown::HashMap;
+use hashbrown::{HashMap, HashSet};
+use itertools::Itertools;
+use std::collections::VecDeque;
 use std::fmt::Display;
 
-const TILE_SIZE: usize = 10;
+use crate::utils::Rng;
 
-#[derive(Debug, Clone, Default)]
-pub struct Tile {
-    id: usize,
-    grid: Vec<Vec<bool>>,
-    edges: Vec<usize>,
+// Edges and pattern rows are stored as bits of a `u64`
+const MAX_TILE_SIZE: usize = 64;
+
+/// Element of the dihedral group D4: a flip left to right if `flipped`,
+/// then `rotation` quarter turns clockwise
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Orientation {
+    rotation: u8,
+    flipped: bool,
 }
 
-impl Display for Tile {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        for v in self.grid.iter() {
-            let s: String = v.iter().map(|b| if *b { "#" } else { "." }).collect();
-            writeln!(f, "{}", s)?
+impl Orientation {
+    /// The 8 orientations, in the order of `Orientation::index`
+    pub const ALL: [Orientation; 8] = [
+        Orientation::new(0, false),
+        Orientation::new(1, false),
+        Orientation::new(2, false),
+        Orientation::new(3, false),
+        Orientation::new(0, true),
+        Orientation::new(1, true),
+        Orientation::new(2, true),
+        Orientation::new(3, true),
+    ];
+
+    pub const fn new(rotation: u8, flipped: bool) -> Self {
+        Self {
+            rotation: rotation % 4,
+            flipped,
+        }
+    }
+
+    /// `0..4` for the rotations, `4..8` for the flipped ones
+    pub fn index(self) -> usize {
+        self.rotation as usize + 4 * self.flipped as usize
+    }
+
+    /// `self` followed by `other`
+    pub fn then(self, other: Self) -> Self {
+        // Flipping reverses the direction of the rotations done before
+        let rotation = if other.flipped {
+            other.rotation + 4 - self.rotation
+        } else {
+            other.rotation + self.rotation
+        };
+        Self::new(rotation, self.flipped != other.flipped)
+    }
+
+    pub fn inverse(self) -> Self {
+        if self.flipped {
+            self
+        } else {
+            Self::new(4 - self.rotation, false)
+        }
+    }
+
+    /// Size of a `rows`x`cols` grid in this orientation
+    fn dims(self, rows: usize, cols: usize) -> (usize, usize) {
+        if self.rotation % 2 == 0 {
+            (rows, cols)
+        } else {
+            (cols, rows)
+        }
+    }
+
+    /// Cell of a `rows`x`cols` grid seen at `(x, y)` in this orientation
+    fn source(self, rows: usize, cols: usize, (x, y): (usize, usize)) -> (usize, usize) {
+        // Undo the rotations, each quarter turn clockwise takes (x, y) from (rows - 1 - y, x)
+        let (x, y) = match self.rotation {
+            0 => (x, y),
+            1 => (rows - 1 - y, x),
+            2 => (rows - 1 - x, cols - 1 - y),
+            _ => (y, cols - 1 - x),
+        };
+        if self.flipped {
+            (x, cols - 1 - y)
+        } else {
+            (x, y)
         }
-        Ok(())
     }
 }
 
-#[derive(Debug, Clone, Copy)]
-pub enum Edge {
-    Top(bool),
-    Bottom(bool),
-    Left(bool),
-    Right(bool),
+/// Copy of a grid in orientation `o`
+fn orient<T: Clone>(grid: &[Vec<T>], o: Orientation) -> Vec<Vec<T>> {
+    let (rows, cols) = (grid.len(), grid.first().map_or(0, Vec::len));
+    let (r, c) = o.dims(rows, cols);
+    (0..r)
+        .map(|x| {
+            (0..c)
+                .map(|y| {
+                    let (i, j) = o.source(rows, cols, (x, y));
+                    grid[i][j].clone()
+                })
+                .collect()
+        })
+        .collect()
 }
 
-use Edge::*;
+/// Rows of bits packed in words, the first column in the most significant bit
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BitGrid {
+    rows: usize,
+    cols: usize,
+    words: Vec<u64>,
+}
 
-impl Tile {
-    fn new(id: usize, grid: Vec<Vec<bool>>) -> Self {
+impl BitGrid {
+    pub fn new(rows: usize, cols: usize) -> Self {
         Self {
-            id,
-            edges: Self::edges(&grid),
-            grid,
+            rows,
+            cols,
+            words: vec![0; rows * Self::stride(cols)],
         }
     }
 
-    // Get vec of edges represented as an integer
-    fn edges(grid: &[Vec<bool>]) -> Vec<usize> {
-        let fold_int = |acc, &b| (acc + b as usize) << 1;
-        let row = |idx: usize| grid[idx].iter().fold(0, fold_int);
-        let col = |idx: usize| grid.iter().map(|cols| &cols[idx]).fold(0, fold_int);
-        let row_rev = |idx: usize| grid[idx].iter().rev().fold(0, fold_int);
-        let col_rev = |idx: usize| grid.iter().rev().map(|cols| &cols[idx]).fold(0, fold_int);
+    pub fn from_rows(rows: &[Vec<bool>]) -> Self {
+        let mut grid = Self::new(rows.len(), rows.first().map_or(0, Vec::len));
+        for (x, row) in rows.iter().enumerate() {
+            for (y, on) in row.iter().enumerate() {
+                grid.set(x, y, *on);
+            }
+        }
+        grid
+    }
 
-        vec![
-            row(0),
-            row(grid.len() - 1),
-            col(0),
-            col(grid.len() - 1),
-            row_rev(0),
-            row_rev(grid.len() - 1),
-            col_rev(0),
-            col_rev(grid.len() - 1),
-        ]
+    // Words per row
+    fn stride(cols: usize) -> usize {
+        (cols + MAX_TILE_SIZE - 1) / MAX_TILE_SIZE
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+
+    fn word(&self, x: usize, y: usize) -> usize {
+        x * Self::stride(self.cols) + y / MAX_TILE_SIZE
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> bool {
+        self.words[self.word(x, y)] >> (63 - y % MAX_TILE_SIZE) & 1 == 1
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, on: bool) {
+        let (word, bit) = (self.word(x, y), 1 << (63 - y % MAX_TILE_SIZE));
+        if on {
+            self.words[word] |= bit;
+        } else {
+            self.words[word] &= !bit;
+        }
+    }
+
+    /// `len` bits of row `x` from column `y`, the first one in the most significant bit.
+    /// Columns past the end are unset.
+    pub fn window(&self, x: usize, y: usize, len: usize) -> u64 {
+        debug_assert!(len > 0 && len <= MAX_TILE_SIZE);
+        let stride = Self::stride(self.cols);
+        let row = &self.words[x * stride..(x + 1) * stride];
+        let (w, offset) = (y / MAX_TILE_SIZE, y % MAX_TILE_SIZE);
+        let mut bits = row.get(w).map_or(0, |b| b << offset);
+        if offset > 0 {
+            bits |= row.get(w + 1).map_or(0, |b| b >> (64 - offset));
+        }
+        bits >> (64 - len)
     }
 
-    fn edge(idx: usize) -> Edge {
-        match idx {
-            0 => Top(false),
-            1 => Bottom(false),
-            2 => Left(false),
-            3 => Right(false),
-            4 => Top(true),
-            5 => Bottom(true),
-            6 => Left(true),
-            7 => Right(true),
-            _ => unreachable!(),
+    /// Write the last `len` bits of `bits` to row `x` from column `y`, the reverse of `window`
+    pub fn put(&mut self, x: usize, y: usize, bits: u64, len: usize) {
+        debug_assert!(len > 0 && len <= MAX_TILE_SIZE && y + len <= self.cols);
+        let (mask, bits) = (u64::MAX << (64 - len), bits << (64 - len));
+        let (i, offset) = (self.word(x, y), y % MAX_TILE_SIZE);
+        self.words[i] = self.words[i] & !(mask >> offset) | bits >> offset;
+        if offset + len > MAX_TILE_SIZE {
+            let back = 64 - offset;
+            self.words[i + 1] = self.words[i + 1] & !(mask << back) | bits << back;
         }
     }
 
-    fn transpose(&mut self) {
-        for i in 0..self.grid.len() {
-            for j in i + 1..self.grid.len() {
-                let tmp = self.grid[i][j];
-                self.grid[i][j] = self.grid[j][i];
-                self.grid[j][i] = tmp;
+    /// Copy `grid` with its top left corner at `(x, y)`
+    pub fn paste(&mut self, x: usize, y: usize, grid: &BitGrid) {
+        for i in 0..grid.rows {
+            for j in (0..grid.cols).step_by(MAX_TILE_SIZE) {
+                let len = (grid.cols - j).min(MAX_TILE_SIZE);
+                self.put(x + i, y + j, grid.window(i, j, len), len);
             }
         }
     }
 
-    fn flip_cols(&mut self) {
-        self.grid.iter_mut().for_each(|row| row.reverse());
-        self.edges = Self::edges(&self.grid);
+    pub fn count_ones(&self) -> usize {
+        self.words.iter().map(|w| w.count_ones() as usize).sum()
     }
 
-    fn flip_rows(&mut self) {
-        self.grid.reverse();
-        self.edges = Self::edges(&self.grid);
+    /// Copy of the grid in orientation `o`
+    pub fn oriented(&self, o: Orientation) -> Self {
+        let (rows, cols) = o.dims(self.rows, self.cols);
+        let mut grid = Self::new(rows, cols);
+        for x in 0..rows {
+            for y in (0..cols).step_by(MAX_TILE_SIZE) {
+                let len = (cols - y).min(MAX_TILE_SIZE);
+                let bits = (y..y + len).fold(0, |acc, y| {
+                    let (i, j) = o.source(self.rows, self.cols, (x, y));
+                    acc << 1 | self.get(i, j) as u64
+                });
+                grid.put(x, y, bits, len);
+            }
+        }
+        grid
     }
 
-    fn rotate(&mut self, n: u8) {
-        // rotate = transpose + flip
-        match n % 4 {
-            0 => {} // no-op
-            1 => {
-                // 90 clockwise
-                self.transpose();
-                self.flip_cols();
+    /// Copy of the `rows`x`cols` cells from `(x, y)`
+    pub fn region(&self, x: usize, y: usize, rows: usize, cols: usize) -> Self {
+        let mut grid = Self::new(rows, cols);
+        for i in 0..rows {
+            for j in (0..cols).step_by(MAX_TILE_SIZE) {
+                let len = (cols - j).min(MAX_TILE_SIZE);
+                grid.put(i, j, self.window(x + i, y + j, len), len);
             }
-            2 => {
-                // 180
-                self.flip_rows();
-                self.flip_cols();
+        }
+        grid
+    }
+
+    /// Each cell set with probability 1/2
+    pub fn random(rows: usize, cols: usize, seed: u64) -> Self {
+        let mut rng = Rng::new(seed);
+        let mut grid = Self::new(rows, cols);
+        for x in 0..rows {
+            for y in 0..cols {
+                grid.set(x, y, rng.next_u64() & 1 == 1);
             }
-            3 => {
-                // counterclockwise
-                self.flip_cols();
-                self.transpose();
+        }
+        grid
+    }
+
+    /// Plain PBM (P1) as written by `Image::write_pbm`, set cells are black
+    pub fn read_pbm(s: &str) -> Result<Self> {
+        // Comments run from `#` to the end of the line
+        let mut chars = s
+            .lines()
+            .flat_map(|l| {
+                l.split('#')
+                    .next()
+                    .unwrap_or_default()
+                    .chars()
+                    .chain(Some('\n'))
+            })
+            .peekable();
+        let mut token = || -> Option<String> {
+            while chars.next_if(|c| c.is_whitespace()).is_some() {}
+            let mut token = String::new();
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                token.push(c);
             }
-            _ => unreachable!("modulo!"),
+            Some(token).filter(|t| !t.is_empty())
+        };
+        if token().as_deref() != Some("P1") {
+            return Err(anyhow!("Not a plain PBM file"));
         }
-        self.edges = Self::edges(&self.grid);
+        let mut dim = || -> Result<usize> {
+            token()
+                .and_then(|t| t.parse().ok())
+                .ok_or_else(|| anyhow!("Invalid PBM size"))
+        };
+        let (cols, rows) = (dim()?, dim()?);
+        // Pixels need not be separated by whitespace
+        let mut pixels = chars.filter(|c| !c.is_whitespace());
+        let mut grid = Self::new(rows, cols);
+        for x in 0..rows {
+            for y in 0..cols {
+                match pixels.next() {
+                    Some('1') => grid.set(x, y, true),
+                    Some('0') => {}
+                    Some(c) => return Err(anyhow!("Invalid PBM pixel: {:?}", c)),
+                    None => return Err(anyhow!("Missing PBM pixels")),
+                }
+            }
+        }
+        Ok(grid)
     }
+}
 
-    /// Match with a tile t and returns the matching edges
-    fn match_tile(&self, t: &Tile) -> Option<(Edge, Edge)> {
-        if let Some((idx, t_idx)) = self.edges[..4].iter().enumerate().find_map(|(idx, e)| {
-            t.edges
-                .iter()
-                .enumerate()
-                .find(|(_, t_e)| t_e == &e)
-                .map(|(t_idx, _)| (idx, t_idx))
-        }) {
-            Some((Self::edge(idx), Self::edge(t_idx)))
-        } else {
-            None
+impl Display for BitGrid {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for x in 0..self.rows {
+            let s: String = (0..self.cols)
+                .map(|y| if self.get(x, y) { '#' } else { '.' })
+                .collect();
+            writeln!(f, "{}", s)?
         }
+        Ok(())
     }
+}
 
-    /// Match a give tile to `self` and rotate/flip it to match orientation
-    /// Return the matched edge of `self`
-    fn match_and_transform(&self, t: &mut Tile) -> Option<Edge> {
-        if let Some((e1, e2)) = self.match_tile(t) {
-            match (e1, e2) {
-                (Top(_), Top(rev)) | (Bottom(_), Bottom(rev)) => {
-                    if !rev {
-                        t.flip_rows();
-                    } else {
-                        t.rotate(2);
-                    }
-                }
-                (Left(_), Left(rev)) | (Right(_), Right(rev)) => {
-                    if !rev {
-                        t.flip_cols();
-                    } else {
-                        t.rotate(2);
-                    }
-                }
-                (Top(_), Bottom(rev)) | (Bottom(_), Top(rev)) => {
-                    if rev {
-                        t.flip_cols();
-                    }
-                }
-                (Left(_), Right(rev)) | (Right(_), Left(rev)) => {
-                    if rev {
-                        t.flip_rows();
-                    }
-                }
-                (Top(_), Left(rev)) | (Bottom(_), Right(rev)) => {
-                    t.rotate(3);
-                    if rev {
-                        t.flip_cols();
-                    }
-                }
-                (Top(_), Right(rev)) | (Bottom(_), Left(rev)) => {
-                    t.rotate(1);
-                    if !rev {
-                        t.flip_cols();
-                    }
-                }
+/// Reverse the order of the last `len` bits
+fn reverse(bits: u64, len: usize) -> u64 {
+    bits.reverse_bits() >> (64 - len)
+}
 
-                (Left(_), Top(rev)) | (Right(_), Bottom(rev)) => {
-                    t.rotate(1);
-                    if rev {
-                        t.flip_rows();
-                    }
-                }
-                (Left(_), Bottom(rev)) | (Right(_), Top(rev)) => {
-                    t.rotate(3);
-                    if !rev {
-                        t.flip_rows()
-                    }
-                }
-            }
-            assert!(matches!(
-                self.match_tile(t).unwrap(),
-                (Top(false), Bottom(false))
-                    | (Bottom(false), Top(false))
-                    | (Left(false), Right(false))
-                    | (Right(false), Left(false))
-            ));
-            Some(e1)
+#[derive(Debug, Clone, Default)]
+pub struct Tile {
+    id: usize,
+    grid: BitGrid,
+    // Indexed by `Side`, read left to right and top to bottom
+    borders: [u64; 4],
+}
+
+impl Display for Tile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.grid)
+    }
+}
+
+/// Side of a tile, indexes `Tile::borders`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Side {
+    Top,
+    Bottom,
+    Left,
+    Right,
+}
+
+impl Side {
+    pub const ALL: [Side; 4] = [Side::Top, Side::Bottom, Side::Left, Side::Right];
+
+    /// Side facing this one on the adjacent tile
+    pub fn opposite(self) -> Self {
+        match self {
+            Side::Top => Side::Bottom,
+            Side::Bottom => Side::Top,
+            Side::Left => Side::Right,
+            Side::Right => Side::Left,
+        }
+    }
+}
+
+impl Tile {
+    fn new(id: usize, grid: BitGrid) -> Self {
+        let last = grid.rows() - 1;
+        let col = |y| (0..=last).fold(0, |acc, x| acc << 1 | grid.get(x, y) as u64);
+        let borders = [
+            grid.window(0, 0, last + 1),
+            grid.window(last, 0, last + 1),
+            col(0),
+            col(last),
+        ];
+        Self { id, grid, borders }
+    }
+
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    fn size(&self) -> usize {
+        self.grid.rows()
+    }
+
+    /// The tile seen in orientation `o`, without copying it
+    pub fn view(&self, o: Orientation) -> TileView<'_> {
+        TileView {
+            tile: self,
+            orientation: o,
+        }
+    }
+
+    /// Border on `side` in the stored orientation, read left to right and top to bottom
+    pub fn border(&self, side: Side) -> u64 {
+        self.borders[side as usize]
+    }
+}
+
+/// A tile in some orientation, cells are looked up in the stored tile
+#[derive(Debug, Clone, Copy)]
+pub struct TileView<'a> {
+    tile: &'a Tile,
+    orientation: Orientation,
+}
+
+impl<'a> TileView<'a> {
+    pub fn id(&self) -> usize {
+        self.tile.id
+    }
+
+    pub fn orientation(&self) -> Orientation {
+        self.orientation
+    }
+
+    /// This view in orientation `o`
+    pub fn oriented(self, o: Orientation) -> Self {
+        self.tile.view(self.orientation.then(o))
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> bool {
+        let n = self.tile.size();
+        let (i, j) = self.orientation.source(n, n, (x, y));
+        self.tile.grid.get(i, j)
+    }
+
+    /// Border on `side` of the view, read left to right and top to bottom
+    fn border(&self, side: Side) -> u64 {
+        let n = self.tile.size();
+        let last = n - 1;
+        let (a, b) = match side {
+            Side::Top => ((0, 0), (0, last)),
+            Side::Bottom => ((last, 0), (last, last)),
+            Side::Left => ((0, 0), (last, 0)),
+            Side::Right => ((0, last), (last, last)),
+        };
+        // The ends of the border tell which stored border it is and in which direction
+        let ((ax, ay), (bx, by)) = (
+            self.orientation.source(n, n, a),
+            self.orientation.source(n, n, b),
+        );
+        let (stored, reversed) = if ax == bx {
+            (if ax == 0 { Side::Top } else { Side::Bottom }, ay > by)
+        } else {
+            (if ay == 0 { Side::Left } else { Side::Right }, ax > bx)
+        };
+        let bits = self.tile.borders[stored as usize];
+        if reversed {
+            reverse(bits, n)
         } else {
-            None
+            bits
         }
     }
+
+    /// Copy of the oriented tile
+    pub fn to_grid(&self) -> BitGrid {
+        self.tile.grid.oriented(self.orientation)
+    }
 }
 
 #[aoc_generator(day20)]
-pub fn input_parser(input: &str) -> Option<Vec<Tile>> {
-    let tiles = input.split("\n\n");
-    tiles
+pub fn input_parser(input: &str) -> Result<Vec<Tile>> {
+    let tiles = input
+        .split("\n\n")
+        .filter(|t| !t.trim().is_empty())
         .map(|t| {
             let mut l = t.lines();
-            let id = l
-                .next()?
-                .strip_prefix("Tile ")?
-                .strip_suffix(":")?
-                .parse()
-                .ok()?;
-            let grid = l.map(|s| s.chars().map(|c| c == '#').collect()).collect();
-            Some(Tile::new(id, grid))
+            let header = l.next().unwrap_or_default();
+            let id = header
+                .strip_prefix("Tile ")
+                .and_then(|s| s.strip_suffix(":"))
+                .and_then(|s| s.parse().ok())
+                .ok_or_else(|| anyhow!("Invalid tile header: {}", header))?;
+            let grid: Vec<Vec<bool>> = l.map(|s| s.chars().map(|c| c == '#').collect()).collect();
+            // Tiles are square
+            if grid.len() < 3 || grid.iter().any(|row| row.len() != grid.len()) {
+                return Err(anyhow!(
+                    "Tile {}: expected a square grid of size 3 or more",
+                    id
+                ));
+            }
+            if grid.len() > MAX_TILE_SIZE {
+                return Err(anyhow!(
+                    "Tiles larger than {} are not supported",
+                    MAX_TILE_SIZE
+                ));
+            }
+            Ok(Tile::new(id, BitGrid::from_rows(&grid)))
         })
-        .collect()
+        .collect::<Result<Vec<_>>>()?;
+
+    let size = tiles.first().ok_or_else(|| anyhow!("No tiles"))?.size();
+    if let Some(t) = tiles.iter().find(|t| t.size() != size) {
+        return Err(anyhow!(
+            "Tile {} is {}x{} but tile {} is {}x{}",
+            t.id,
+            t.size(),
+            t.size(),
+            tiles[0].id,
+            size,
+            size
+        ));
+    }
+    Ok(tiles)
 }
 
-#[aoc(day20, part1)]
-pub fn part1(tiles: &[Tile]) -> usize {
-    let mut match_count = vec![0; tiles.len()];
-    for i in 0..tiles.len() {
-        for j in (0..tiles.len()).filter(|j| *j != i) {
-            match_count[i] += tiles[i].match_tile(&tiles[j]).is_some() as usize;
+/// Position of a tile in an assembled image, from how many of its sides match another tile
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TileKind {
+    Corner,
+    Border,
+    Interior,
+}
+
+/// Sides of the tiles by canonical border: the smaller of the border and its reverse
+#[derive(Debug, Default)]
+pub struct EdgeIndex {
+    size: usize,
+    // (tile, side, flipped): flipped if the canonical border is the reverse of the stored one
+    sides: HashMap<u64, Vec<(usize, Side, bool)>>,
+}
+
+impl EdgeIndex {
+    pub fn new(tiles: &[Tile]) -> Self {
+        let size = tiles.first().map_or(0, Tile::size);
+        let mut index = Self {
+            size,
+            sides: HashMap::with_capacity(2 * tiles.len()),
+        };
+        for (idx, tile) in tiles.iter().enumerate() {
+            for &side in Side::ALL.iter() {
+                let (canonical, flipped) = index.canonical(tile.border(side));
+                let entry = index.sides.entry(canonical).or_default();
+                entry.push((idx, side, flipped));
+            }
         }
+        index
+    }
+
+    fn canonical(&self, border: u64) -> (u64, bool) {
+        let rev = reverse(border, self.size);
+        (border.min(rev), rev < border)
+    }
+
+    /// Sides of any tile with `border` in either direction
+    pub fn sides(&self, border: u64) -> &[(usize, Side, bool)] {
+        let (canonical, _) = self.canonical(border);
+        self.sides.get(&canonical).map_or(&[], Vec::as_slice)
+    }
+
+    /// Number of other tiles with border `border` in either direction, apart from tile `idx`
+    fn others(&self, border: u64, idx: usize) -> usize {
+        self.sides(border)
+            .iter()
+            .filter(|(i, _, _)| *i != idx)
+            .count()
+    }
+
+    /// Number of sides of tile `idx` that match a side of another tile
+    pub fn matched_sides(&self, tiles: &[Tile], idx: usize) -> usize {
+        Side::ALL
+            .iter()
+            .filter(|side| self.others(tiles[idx].border(**side), idx) > 0)
+            .count()
+    }
+
+    /// Kind of each tile, `None` for tiles with fewer than 2 matched sides
+    pub fn classify(&self, tiles: &[Tile]) -> Vec<Option<TileKind>> {
+        (0..tiles.len())
+            .map(|idx| match self.matched_sides(tiles, idx) {
+                2 => Some(TileKind::Corner),
+                3 => Some(TileKind::Border),
+                4 => Some(TileKind::Interior),
+                _ => None,
+            })
+            .collect()
     }
-    match_count
+}
+
+#[aoc(day20, part1)]
+pub fn part1(tiles: &[Tile]) -> usize {
+    let index = EdgeIndex::new(tiles);
+    index
+        .classify(tiles)
         .iter()
-        .enumerate()
-        .filter(|(_idx, cnt)| **cnt == 2)
-        .map(|(idx, _)| tiles[idx].id)
+        .zip(tiles)
+        .filter(|(kind, _)| **kind == Some(TileKind::Corner))
+        .map(|(_, t)| t.id)
         .product()
 }
 
 #[derive(Debug, Default)]
 pub struct Image {
-    map: HashMap<(isize, isize), Tile>,
-    pub image: Tile,
+    // Tile and its orientation at each position
+    map: HashMap<(isize, isize), (Tile, Orientation)>,
+    pub image: BitGrid,
+    // Cells of the missing tiles, empty if none are missing
+    gaps: BitGrid,
+}
+
+/// Result of `Image::assemble_partial`
+#[derive(Debug, Default)]
+pub struct PartialAssembly {
+    pub image: Image,
+    /// Ids of the tiles left out, in input order: missing neighbours, decoys and duplicates
+    pub unplaced: Vec<usize>,
+    /// Positions in the arrangement without a tile, as (row, col)
+    pub gaps: Vec<(usize, usize)>,
 }
 
 impl Image {
-    fn from_tiles(mut tiles: Vec<Tile>) -> Self {
+    /// First assembly found, see `Image::assemblies`
+    fn from_tiles(tiles: Vec<Tile>) -> Result<Self> {
+        Self::assemblies(&tiles, 1)?
+            .pop()
+            .ok_or_else(|| anyhow!("No assembly"))
+    }
+
+    /// Up to `limit` distinct assemblies of all the tiles into a rectangle.
+    /// Assemblies that only differ by a rotation or flip of the whole image are the same.
+    /// With unique borders, 100 by 100 tiles assemble in about 50ms in release builds.
+    pub fn assemblies(tiles: &[Tile], limit: usize) -> Result<Vec<Self>> {
+        let n = tiles.len();
+        let assembler = Assembler::new(tiles);
+        let mut seen = HashSet::new();
+        let mut images = Vec::new();
+        // Arrangements with more rows than columns are rotations of the others
+        let mut arrangements: Vec<usize> = (1..=n)
+            .filter(|rows| n % rows == 0 && rows * rows <= n)
+            .collect();
+        // With 4 corners, the corners and borders give rows + cols: try that arrangement first
+        let kinds = assembler.index.classify(tiles);
+        let count = |kind| kinds.iter().filter(|k| **k == Some(kind)).count();
+        if count(TileKind::Corner) == 4 {
+            let perimeter = (count(TileKind::Border) + 8) / 2;
+            arrangements.sort_by_key(|rows| rows + n / rows != perimeter);
+        }
+        for rows in arrangements {
+            let cols = n / rows;
+            assembler.search(rows, cols, &mut |assembler, placed| {
+                // A single assembly needs no deduplication
+                if limit == 1 || seen.insert(assembler.canonical(placed, cols)) {
+                    images.push(assembler.image(placed, cols));
+                }
+                images.len() < limit
+            });
+            if images.len() >= limit {
+                break;
+            }
+        }
+        if images.is_empty() {
+            return Err(anyhow!(
+                "The {} tiles cannot be assembled into a rectangle",
+                n
+            ));
+        }
+        Ok(images)
+    }
+
+    /// Largest assembly consistent with the tiles when some are missing, duplicated or
+    /// match nothing, among those grown from each tile in each orientation.
+    /// Missing positions are left as gaps in the image.
+    pub fn assemble_partial(tiles: &[Tile]) -> Result<PartialAssembly> {
+        let n = tiles.len();
+        if n == 0 {
+            return Err(anyhow!("No tiles"));
+        }
+        let assembler = Assembler::new(tiles);
+        let matched = |idx: &usize| assembler.index.matched_sides(tiles, *idx);
+        // No assembly is larger: tiles matching nothing stand alone, copies go in once
+        let most = (0..n)
+            .filter(|idx| matched(idx) > 0)
+            .map(|idx| tiles[idx].id)
+            .unique()
+            .count()
+            .max(1);
+        // A greedy choice can shut real tiles out, so grow from every tile in every
+        // orientation, best connected tiles first, until no larger assembly can exist
+        let seeds = (0..n).sorted_by_key(|idx| 4 - matched(idx));
+        let mut best = HashMap::new();
+        for (seed, o) in seeds.cartesian_product(Orientation::ALL.iter()) {
+            let placed = assembler.grow((seed, *o));
+            if placed.len() > best.len() {
+                best = placed;
+            }
+            if best.len() >= most {
+                break;
+            }
+        }
+
+        let x0 = best.keys().map(|(x, _)| *x).min().unwrap_or(0);
+        let y0 = best.keys().map(|(_, y)| *y).min().unwrap_or(0);
         let mut image = Image::default();
-        let mut queue = vec![(0, 0, 0)];
-        let mut seen = Vec::new();
-        while let Some((cur_idx, x, y)) = queue.pop() {
-            if seen.contains(&cur_idx) {
-                continue;
-            }
-            seen.push(cur_idx);
-
-            let tile = tiles[cur_idx].clone();
-            for (idx, next_tile) in tiles
-                .iter_mut()
-                .enumerate()
-                .filter(|(idx, _)| !seen.contains(idx))
-            {
-                let matched_edge = tile.match_and_transform(next_tile);
-                if let Some(edge) = matched_edge {
-                    let (x1, y1) = match edge {
-                        Top(_) => (x - 1, y),
-                        Bottom(_) => (x + 1, y),
-                        Left(_) => (x, y - 1),
-                        Right(_) => (x, y + 1),
-                    };
-                    queue.push((idx, x1, y1));
+        for ((x, y), (idx, o)) in &best {
+            image
+                .map
+                .insert((x - x0, y - y0), (tiles[*idx].clone(), *o));
+        }
+        image.stitch();
+        let (rows, cols) = image.dims();
+        let gaps = (0..rows)
+            .flat_map(|x| (0..cols).map(move |y| (x, y)))
+            .filter(|(x, y)| !image.map.contains_key(&(*x as isize, *y as isize)))
+            .collect();
+        let placed: HashSet<usize> = best.values().map(|(idx, _)| *idx).collect();
+        let unplaced = (0..n)
+            .filter(|idx| !placed.contains(idx))
+            .map(|idx| tiles[idx].id)
+            .collect();
+        Ok(PartialAssembly {
+            image,
+            unplaced,
+            gaps,
+        })
+    }
+
+    /// Arrangement size as (rows, cols) of tiles, from the origin to the furthest tile
+    fn dims(&self) -> (usize, usize) {
+        let rows = self.map.keys().map(|(x, _)| x + 1).max().unwrap_or(0);
+        let cols = self.map.keys().map(|(_, y)| y + 1).max().unwrap_or(0);
+        (rows.max(0) as usize, cols.max(0) as usize)
+    }
+
+    /// Image from the tiles without their borders. Missing positions are left unset.
+    fn stitch(&mut self) {
+        let (rows, cols) = self.dims();
+        let tile_size = self.map.values().next().map_or(2, |(t, _)| t.size());
+        let size_wo_edges = tile_size - 2;
+
+        self.image = BitGrid::new(rows * size_wo_edges, cols * size_wo_edges);
+        self.gaps = BitGrid::default();
+        let full = BitGrid::from_rows(&vec![vec![true; size_wo_edges]; size_wo_edges]);
+        for x in 0..rows {
+            for y in 0..cols {
+                let (x0, y0) = (x * size_wo_edges, y * size_wo_edges);
+                if let Some((tile, o)) = self.map.get(&(x as isize, y as isize)) {
+                    let inner = tile
+                        .view(*o)
+                        .to_grid()
+                        .region(1, 1, size_wo_edges, size_wo_edges);
+                    self.image.paste(x0, y0, &inner);
+                } else if size_wo_edges > 0 {
+                    if self.gaps.rows() == 0 {
+                        self.gaps = BitGrid::new(self.image.rows(), self.image.cols());
+                    }
+                    self.gaps.paste(x0, y0, &full);
                 }
             }
-            image.map.insert((x, y), tile);
         }
-        image.recenter();
-        image.to_tile();
-        image
     }
 
-    /// Recenter the Hashmap so that the image is on (0.., 0..)
-    fn recenter(&mut self) {
-        let &(min_x, min_y) = self.map.keys().min_by_key(|(x, y)| x + y).unwrap();
-        let mut map = HashMap::with_capacity(self.map.len());
-        for ((x, y), t) in self.map.drain() {
-            map.insert((x - min_x, y - min_y), t);
+    /// Whether the cell belongs to a missing tile
+    pub fn is_gap(&self, x: usize, y: usize) -> bool {
+        self.gaps.rows() > 0 && self.gaps.get(x, y)
+    }
+
+    /// Every match of the patterns in any orientation, in row-major order.
+    /// Unless `overlapping`, a match sharing a set cell with a previous one is skipped.
+    pub fn find_patterns(&self, patterns: &[Pattern], overlapping: bool) -> Vec<Match> {
+        let grid = &self.image;
+        let oriented: Vec<_> = patterns
+            .iter()
+            .enumerate()
+            .flat_map(|(idx, p)| {
+                let transforms: Vec<_> = Orientation::ALL
+                    .iter()
+                    .map(|o| orient(&p.cells, *o))
+                    .collect();
+                // Symmetric patterns look the same in several orientations: keep the first one
+                (0..8)
+                    .filter(|&o| !transforms[..o].contains(&transforms[o]))
+                    .map(|o| (idx, Orientation::ALL[o], Masks::new(&transforms[o])))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+
+        let mut covered = HashSet::new();
+        let mut matches = Vec::new();
+        for x in 0..grid.rows() {
+            for y in 0..grid.cols() {
+                for (pattern, orientation, masks) in &oriented {
+                    if !masks.matches_at(grid, &self.gaps, x, y) {
+                        continue;
+                    }
+                    let m = Match {
+                        pattern: *pattern,
+                        orientation: *orientation,
+                        x,
+                        y,
+                    };
+                    let cells = masks.set_cells(x, y);
+                    if !overlapping && cells.iter().any(|c| covered.contains(c)) {
+                        continue;
+                    }
+                    covered.extend(cells);
+                    matches.push(m);
+                }
+            }
         }
-        self.map = map;
+        matches
+    }
+
+    /// Cells set in the pattern of each match
+    pub fn covered_cells(
+        &self,
+        patterns: &[Pattern],
+        matches: &[Match],
+    ) -> HashSet<(usize, usize)> {
+        matches
+            .iter()
+            .flat_map(|m| {
+                Masks::new(&orient(&patterns[m.pattern].cells, m.orientation)).set_cells(m.x, m.y)
+            })
+            .collect()
+    }
+
+    /// Set cells of the image that no match covers
+    pub fn roughness(&self, patterns: &[Pattern], matches: &[Match]) -> usize {
+        self.image.count_ones() - self.covered_cells(patterns, matches).len()
     }
+}
+
+/// Cell of a pattern: `Some(true)` must be set, `Some(false)` must be unset, `None` is a wildcard
+pub type PatternCell = Option<bool>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pattern {
+    cells: Vec<Vec<PatternCell>>,
+}
+
+impl std::str::FromStr for Pattern {
+    type Err = anyhow::Error;
 
-    fn to_tile(&mut self) {
-        let mut dim = 0;
-        while let Some(_) = self.map.get(&(dim as isize, dim as isize)) {
-            dim += 1
+    /// `#` must be set, `.` must be unset, any other character is a wildcard
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut cells: Vec<Vec<PatternCell>> = s
+            .lines()
+            .map(|l| {
+                l.chars()
+                    .map(|c| match c {
+                        '#' => Some(true),
+                        '.' => Some(false),
+                        _ => None,
+                    })
+                    .collect()
+            })
+            .collect();
+        let width = cells.iter().map(Vec::len).max().unwrap_or(0);
+        if width == 0 {
+            return Err(anyhow!("Empty pattern"));
         }
+        // Rotated patterns are as wide as they are high
+        if width.max(cells.len()) > MAX_TILE_SIZE {
+            return Err(anyhow!(
+                "Patterns larger than {} are not supported",
+                MAX_TILE_SIZE
+            ));
+        }
+        cells.iter_mut().for_each(|row| row.resize(width, None));
+        Ok(Pattern { cells })
+    }
+}
 
-        let size_wo_edges = TILE_SIZE - 2;
+/// Rows of an oriented pattern as masks of the cells that must be set and unset,
+/// tested against `BitGrid::window`
+struct Masks {
+    width: usize,
+    rows: Vec<(u64, u64)>,
+}
 
-        self.image = Tile {
-            grid: vec![Vec::with_capacity(dim * size_wo_edges); dim * size_wo_edges],
-            ..Tile::default()
+impl Masks {
+    fn new(cells: &[Vec<PatternCell>]) -> Self {
+        let fold = |(set, unset): (u64, u64), c: &PatternCell| {
+            (
+                set << 1 | (*c == Some(true)) as u64,
+                unset << 1 | (*c == Some(false)) as u64,
+            )
         };
-        for x in 0..dim {
-            for y in 0..dim {
-                let tile = self.map.remove(&(x as isize, y as isize)).unwrap();
-                for (idx, row) in tile.grid.iter().skip(1).take(size_wo_edges).enumerate() {
-                    self.image.grid[x * size_wo_edges + idx]
-                        .extend(row.iter().skip(1).take(size_wo_edges))
+        Self {
+            width: cells.first().map_or(0, Vec::len),
+            rows: cells
+                .iter()
+                .map(|row| row.iter().fold((0, 0), fold))
+                .collect(),
+        }
+    }
+
+    /// Only wildcards can fall in the `gaps`, if there are any
+    fn matches_at(&self, grid: &BitGrid, gaps: &BitGrid, x: usize, y: usize) -> bool {
+        // The pattern's bounding box must fit
+        x + self.rows.len() <= grid.rows()
+            && y + self.width <= grid.cols()
+            && self.rows.iter().enumerate().all(|(dx, (set, unset))| {
+                let bits = grid.window(x + dx, y, self.width);
+                let known =
+                    gaps.rows() == 0 || gaps.window(x + dx, y, self.width) & (set | unset) == 0;
+                known && bits & set == *set && bits & unset == 0
+            })
+    }
+
+    fn set_cells(&self, x: usize, y: usize) -> Vec<(usize, usize)> {
+        self.rows
+            .iter()
+            .enumerate()
+            .flat_map(|(dx, (set, _))| {
+                (0..self.width)
+                    .filter(move |dy| set >> (self.width - 1 - dy) & 1 == 1)
+                    .map(move |dy| (x + dx, y + dy))
+            })
+            .collect()
+    }
+}
+
+/// Pattern found with its top left corner at row `x` and column `y` of the image
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Match {
+    pub pattern: usize,
+    pub orientation: Orientation,
+    pub x: usize,
+    pub y: usize,
+}
+
+// Validation
+
+/// Problem found by `Image::validate`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    /// The border on `side` of tile `first` differs from the facing border of its neighbour `second`
+    Mismatch {
+        first: usize,
+        second: usize,
+        side: Side,
+    },
+    /// Input tile missing from the image
+    Unplaced(usize),
+    /// Tile placed more than once
+    Duplicate { id: usize, count: usize },
+    /// Placed tile that is not an input tile
+    Unknown(usize),
+    /// Position without a tile in the arrangement
+    Gap { row: usize, col: usize },
+    /// Tile placed above or left of the arrangement
+    Outside { id: usize, position: (isize, isize) },
+    /// Side of a tile on the edge of the image that matches another tile
+    Dangling { id: usize, side: Side },
+}
+
+impl Problem {
+    /// Tiles involved in the problem
+    pub fn tiles(&self) -> Vec<usize> {
+        match self {
+            Problem::Mismatch { first, second, .. } => vec![*first, *second],
+            Problem::Unplaced(id)
+            | Problem::Duplicate { id, .. }
+            | Problem::Unknown(id)
+            | Problem::Outside { id, .. }
+            | Problem::Dangling { id, .. } => vec![*id],
+            Problem::Gap { .. } => vec![],
+        }
+    }
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Problem::Mismatch {
+                first,
+                second,
+                side,
+            } => write!(
+                f,
+                "{:?} border of {} does not match {}",
+                side, first, second
+            ),
+            Problem::Unplaced(id) => write!(f, "{} is not placed", id),
+            Problem::Duplicate { id, count } => write!(f, "{} is placed {} times", id, count),
+            Problem::Unknown(id) => write!(f, "{} is not an input tile", id),
+            Problem::Gap { row, col } => write!(f, "No tile at row {}, col {}", row, col),
+            Problem::Outside { id, position } => {
+                write!(f, "{} is outside the arrangement at {:?}", id, position)
+            }
+            Problem::Dangling { id, side } => {
+                write!(f, "{:?} border of {} matches an unplaced side", side, id)
+            }
+        }
+    }
+}
+
+/// Every problem of an assembled image
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Report {
+    pub problems: Vec<Problem>,
+}
+
+impl Report {
+    pub fn is_valid(&self) -> bool {
+        self.problems.is_empty()
+    }
+
+    /// Problems by tile id, problems without a tile are under `None`
+    pub fn by_tile(&self) -> HashMap<Option<usize>, Vec<&Problem>> {
+        let mut by_tile: HashMap<_, Vec<_>> = HashMap::new();
+        for p in &self.problems {
+            let ids = p.tiles();
+            if ids.is_empty() {
+                by_tile.entry(None).or_default().push(p);
+            }
+            for id in ids {
+                by_tile.entry(Some(id)).or_default().push(p);
+            }
+        }
+        by_tile
+    }
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_valid() {
+            return writeln!(f, "Valid assembly");
+        }
+        let by_tile = self.by_tile();
+        for id in by_tile.keys().sorted() {
+            match id {
+                Some(id) => writeln!(f, "Tile {}:", id)?,
+                None => writeln!(f, "Arrangement:")?,
+            }
+            for p in &by_tile[id] {
+                writeln!(f, "  {}", p)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Image {
+    /// Check the image against the input `tiles`: adjacent borders match, every tile is placed
+    /// once in a full rectangle and no side on the edge of the image matches another tile
+    pub fn validate(&self, tiles: &[Tile]) -> Report {
+        let mut problems = Vec::new();
+        let (rows, cols) = self.dims();
+        let index = EdgeIndex::new(tiles);
+        let input: HashMap<usize, usize> =
+            tiles.iter().enumerate().map(|(i, t)| (t.id, i)).collect();
+
+        let mut placed: HashMap<usize, usize> = HashMap::new();
+        for (t, _) in self.map.values() {
+            *placed.entry(t.id).or_default() += 1;
+        }
+        for t in tiles {
+            match placed.get(&t.id) {
+                None => problems.push(Problem::Unplaced(t.id)),
+                Some(&count) if count > 1 => problems.push(Problem::Duplicate { id: t.id, count }),
+                _ => {}
+            }
+        }
+        for (&(x, y), (tile, _)) in self.map.iter().sorted_by_key(|(pos, _)| **pos) {
+            if !input.contains_key(&tile.id) {
+                problems.push(Problem::Unknown(tile.id));
+            }
+            if x < 0 || y < 0 {
+                problems.push(Problem::Outside {
+                    id: tile.id,
+                    position: (x, y),
+                });
+            }
+        }
+
+        for x in 0..rows as isize {
+            for y in 0..cols as isize {
+                let (tile, o) = match self.map.get(&(x, y)) {
+                    Some(placed) => placed,
+                    None => {
+                        problems.push(Problem::Gap {
+                            row: x as usize,
+                            col: y as usize,
+                        });
+                        continue;
+                    }
+                };
+                let view = tile.view(*o);
+                for &(side, pos) in Assembler::neighbours((x, y)).iter() {
+                    let border = view.border(side);
+                    let inside =
+                        (0..rows as isize).contains(&pos.0) && (0..cols as isize).contains(&pos.1);
+                    if !inside {
+                        // Another tile could go there
+                        let idx = input.get(&tile.id).copied().unwrap_or(usize::MAX);
+                        if index.others(border, idx) > 0 {
+                            problems.push(Problem::Dangling { id: tile.id, side });
+                        }
+                        continue;
+                    }
+                    // Each pair once, from the tile above or left
+                    match (side, self.map.get(&pos)) {
+                        (Side::Bottom, Some((other, other_o)))
+                        | (Side::Right, Some((other, other_o)))
+                            if other.view(*other_o).border(side.opposite()) != border =>
+                        {
+                            problems.push(Problem::Mismatch {
+                                first: tile.id,
+                                second: other.id,
+                                side,
+                            });
+                        }
+                        _ => {}
+                    }
                 }
             }
         }
+        Report { problems }
+    }
+}
+
+// Export
+
+/// Overlays for the image exports
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ExportOptions {
+    /// Separate tiles with lines
+    pub boundaries: bool,
+    /// Write tile ids in the top left corner of each tile
+    pub ids: bool,
+    /// Pixels per cell for PPM, 1 if 0
+    pub scale: usize,
+}
+
+type Rgb = (u8, u8, u8);
+
+const SEA: Rgb = (0, 40, 90);
+const WAVE: Rgb = (180, 220, 255);
+const HIGHLIGHT: Rgb = (0, 200, 0);
+const BOUNDARY: Rgb = (100, 100, 100);
+const ID: Rgb = (255, 200, 0);
+const GAP: Rgb = (0, 0, 0);
+
+// 3x5 digits, row by row
+const DIGITS: [&str; 10] = [
+    "####.##.##.####",
+    ".#.##..#..#.###",
+    "###..#####..###",
+    "###..####..####",
+    "#.##.####..#..#",
+    "####..###..####",
+    "####..####.####",
+    "###..#..#..#..#",
+    "####.#####.####",
+    "####.####..####",
+];
+
+impl Image {
+    /// Interior size of the tiles and tile ids by position, if the image was assembled
+    fn tile_layout(&self) -> Option<(usize, Vec<Vec<usize>>)> {
+        let inner = self.map.values().next()?.0.size() - 2;
+        let (rows, cols) = self.dims();
+        let ids = (0..rows as isize)
+            .map(|x| {
+                (0..cols as isize)
+                    .map(|y| self.map.get(&(x, y)).map_or(0, |(t, _)| t.id))
+                    .collect()
+            })
+            .collect();
+        Some((inner, ids))
+    }
+
+    /// Plain PBM (P1): set cells are black
+    pub fn write_pbm(&self, w: &mut impl std::io::Write) -> std::io::Result<()> {
+        let grid = &self.image;
+        writeln!(w, "P1\n{} {}", grid.cols(), grid.rows())?;
+        for x in 0..grid.rows() {
+            let bits: Vec<u8> = (0..grid.cols())
+                .map(|y| if grid.get(x, y) { b'1' } else { b'0' })
+                .collect();
+            // Plain netpbm lines are at most 70 characters
+            for chunk in bits.chunks(70) {
+                w.write_all(chunk)?;
+                writeln!(w)?;
+            }
+        }
+        Ok(())
     }
 
-    fn count_sea_monsters(&self, sea_monster_coords: &[(usize, usize)]) -> usize {
-        let mut count = 0;
-        for x in 0..self.image.grid.len() {
-            for y in 0..self.image.grid.len() {
-                if sea_monster_coords.iter().all(|(dx, dy)| {
-                    if let Some(row) = self.image.grid.get(x + dx) {
-                        if let Some(true) = row.get(y + dy) {
-                            return true;
+    /// Pixels of the image with `highlight`ed cells and the overlays of `options`
+    fn render(
+        &self,
+        highlight: &HashSet<(usize, usize)>,
+        options: &ExportOptions,
+    ) -> Vec<Vec<Rgb>> {
+        let grid = &self.image;
+        let scale = options.scale.max(1);
+        let layout = self.tile_layout().filter(|(inner, _)| *inner > 0);
+        // Pixel offset of cell `i` with the separators of the tiles before it
+        let sep = |i: usize| match &layout {
+            Some((inner, _)) if options.boundaries => i / inner,
+            _ => 0,
+        };
+        let px = |i: usize| i * scale + sep(i);
+        let size = |n: usize| if n == 0 { 0 } else { px(n - 1) + scale };
+        let mut pixels = vec![vec![BOUNDARY; size(grid.cols())]; size(grid.rows())];
+        for x in 0..grid.rows() {
+            for y in 0..grid.cols() {
+                let color = match (highlight.contains(&(x, y)), grid.get(x, y)) {
+                    _ if self.is_gap(x, y) => GAP,
+                    (true, _) => HIGHLIGHT,
+                    (false, true) => WAVE,
+                    (false, false) => SEA,
+                };
+                for p in &mut pixels[px(x)..px(x) + scale] {
+                    p[px(y)..px(y) + scale].iter_mut().for_each(|c| *c = color);
+                }
+            }
+        }
+        if let (true, Some((inner, ids))) = (options.ids, &layout) {
+            for (tx, row) in ids.iter().enumerate() {
+                for (ty, id) in row.iter().enumerate() {
+                    let (x0, y0) = (px(tx * inner), px(ty * inner));
+                    let (x1, y1) = (x0 + inner * scale, y0 + inner * scale);
+                    // Digits are 3 pixels wide with a 1 pixel gap, clipped to the tile
+                    for (d, digit) in id.to_string().bytes().enumerate() {
+                        let font = DIGITS[(digit - b'0') as usize].as_bytes();
+                        for (i, on) in font.iter().enumerate() {
+                            let (x, y) = (x0 + 1 + i / 3, y0 + 1 + 4 * d + i % 3);
+                            if *on == b'#' && x < x1 && y < y1 {
+                                pixels[x][y] = ID;
+                            }
                         }
                     }
-                    false
-                }) {
-                    count += 1
                 }
             }
         }
-        count
+        pixels
+    }
+
+    /// Plain PPM (P3) with `highlight`ed cells, e.g. `Image::covered_cells`
+    pub fn write_ppm(
+        &self,
+        w: &mut impl std::io::Write,
+        highlight: &HashSet<(usize, usize)>,
+        options: &ExportOptions,
+    ) -> std::io::Result<()> {
+        let pixels = self.render(highlight, options);
+        let width = pixels.first().map_or(0, Vec::len);
+        writeln!(w, "P3\n{} {}\n255", width, pixels.len())?;
+        for (r, g, b) in pixels.iter().flatten() {
+            writeln!(w, "{} {} {}", r, g, b)?;
+        }
+        Ok(())
     }
 
-    fn transform_and_count(&mut self, sea_monster_coords: &[(usize, usize)]) -> usize {
-        let rotate_lookup = |s: &mut Self| {
-            for _ in 0..3 {
-                let cnt = s.count_sea_monsters(sea_monster_coords);
-                if cnt > 0 {
-                    return cnt;
+    /// Coloured `#`/`.` text with `highlight`ed cells as `O`
+    pub fn write_ansi(
+        &self,
+        w: &mut impl std::io::Write,
+        highlight: &HashSet<(usize, usize)>,
+        options: &ExportOptions,
+    ) -> std::io::Result<()> {
+        const RESET: &str = "\x1b[0m";
+        let layout = self.tile_layout().filter(|(inner, _)| *inner > 0);
+        let inner = layout.as_ref().map(|(inner, _)| *inner);
+        let boundary =
+            |i: usize| options.boundaries && i > 0 && inner.map_or(false, |n| i % n == 0);
+        let width = self.image.cols();
+        for x in 0..self.image.rows() {
+            if boundary(x) {
+                let line: String = (0..width)
+                    .map(|y| if boundary(y) { "+-" } else { "-" })
+                    .collect();
+                writeln!(w, "\x1b[90m{}{}", line, RESET)?;
+            }
+            if let (true, Some((inner, ids))) = (options.ids, &layout) {
+                if x % inner == 0 {
+                    let labels: Vec<String> = ids[x / inner]
+                        .iter()
+                        .map(|id| format!("{:<1$.1$}", id.to_string(), inner))
+                        .collect();
+                    let sep = if options.boundaries { "|" } else { "" };
+                    writeln!(w, "\x1b[33m{}{}", labels.join(sep), RESET)?;
+                }
+            }
+            for y in 0..width {
+                if boundary(y) {
+                    write!(w, "\x1b[90m|")?;
+                }
+                match (highlight.contains(&(x, y)), self.image.get(x, y)) {
+                    _ if self.is_gap(x, y) => write!(w, " ")?,
+                    (true, _) => write!(w, "\x1b[1;32mO")?,
+                    (false, true) => write!(w, "\x1b[36m#")?,
+                    (false, false) => write!(w, "\x1b[34m.")?,
                 }
-                s.image.rotate(1);
             }
-            0
+            writeln!(w, "{}", RESET)?;
+        }
+        Ok(())
+    }
+}
+
+// Jigsaw puzzles
+
+/// How the borders shared by adjacent tiles are drawn when cutting a bitmap
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Borders {
+    /// As in the bitmap
+    Keep,
+    /// Redrawn so that no two borders match, even reversed
+    Unique,
+    /// Redrawn from this many random patterns, so that unrelated tiles share borders
+    Pool(usize),
+}
+
+/// Where a tile of a `Jigsaw` comes from: it is the piece at `row` and `col`
+/// of the bitmap turned by `orientation`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Piece {
+    pub id: usize,
+    pub row: usize,
+    pub col: usize,
+    pub orientation: Orientation,
+}
+
+/// A bitmap cut into scrambled tiles
+#[derive(Debug, Clone)]
+pub struct Jigsaw {
+    pub tiles: Vec<Tile>,
+    /// Ground truth of each tile, in the same order
+    pub pieces: Vec<Piece>,
+    /// The bitmap without the tile borders, what assembling the tiles gives back
+    pub image: BitGrid,
+}
+
+impl Jigsaw {
+    /// Cut `bitmap` into `size`x`size` tiles that share their borders with their neighbours,
+    /// then turn each tile randomly, give it a random id and shuffle them.
+    /// Cells past the last full tile are dropped.
+    pub fn cut(bitmap: &BitGrid, size: usize, borders: Borders, seed: u64) -> Result<Self> {
+        if !(3..=MAX_TILE_SIZE).contains(&size) {
+            return Err(anyhow!("Tile size must be between 3 and {}", MAX_TILE_SIZE));
+        }
+        let step = size - 1;
+        let rows = bitmap.rows().saturating_sub(1) / step;
+        let cols = bitmap.cols().saturating_sub(1) / step;
+        if rows == 0 || cols == 0 {
+            return Err(anyhow!(
+                "A {}x{} bitmap is too small for {}x{} tiles",
+                bitmap.rows(),
+                bitmap.cols(),
+                size,
+                size
+            ));
+        }
+        let mut rng = Rng::new(seed);
+        let mut bitmap = bitmap.region(0, 0, rows * step + 1, cols * step + 1);
+        Self::redraw_borders(&mut bitmap, size, borders, &mut rng)?;
+
+        let n = rows * cols;
+        // AoC ids have 4 digits, unless there are too many tiles
+        let span = 9000.max(10 * n) as u128;
+        let mut ids = HashSet::new();
+        let mut pieces = Vec::with_capacity(n);
+        for row in 0..rows {
+            for col in 0..cols {
+                let id = loop {
+                    let id = 1000 + rng.below(span) as usize;
+                    if ids.insert(id) {
+                        break id;
+                    }
+                };
+                let orientation = Orientation::ALL[rng.below(8) as usize];
+                pieces.push(Piece {
+                    id,
+                    row,
+                    col,
+                    orientation,
+                });
+            }
+        }
+        // Fisher-Yates
+        for i in (1..n).rev() {
+            pieces.swap(i, rng.below(i as u128 + 1) as usize);
+        }
+
+        let tiles = pieces
+            .iter()
+            .map(|p| {
+                let piece = bitmap.region(p.row * step, p.col * step, size, size);
+                Tile::new(p.id, piece.oriented(p.orientation))
+            })
+            .collect();
+        let inner = size - 2;
+        let mut image = BitGrid::new(rows * inner, cols * inner);
+        for x in 0..image.rows() {
+            for y in 0..image.cols() {
+                let (bx, by) = (
+                    x / inner * step + x % inner + 1,
+                    y / inner * step + y % inner + 1,
+                );
+                image.set(x, y, bitmap.get(bx, by));
+            }
+        }
+        Ok(Self {
+            tiles,
+            pieces,
+            image,
+        })
+    }
+
+    /// Redraw the cells between the corners of the tile borders, the corners are kept.
+    /// Border cells are not part of the assembled image.
+    fn redraw_borders(
+        bitmap: &mut BitGrid,
+        size: usize,
+        borders: Borders,
+        rng: &mut Rng,
+    ) -> Result<()> {
+        let step = size - 1;
+        // First cell and direction of each border
+        let horizontal = (0..bitmap.rows()).step_by(step).flat_map(|x| {
+            (0..bitmap.cols() - 1)
+                .step_by(step)
+                .map(move |y| (x, y, (0, 1)))
+        });
+        let vertical = (0..bitmap.rows() - 1).step_by(step).flat_map(|x| {
+            (0..bitmap.cols())
+                .step_by(step)
+                .map(move |y| (x, y, (1, 0)))
+        });
+        let segments: Vec<_> = horizontal.chain(vertical).collect();
+
+        // Random bits for the `size - 2` cells between the corners
+        let mut random = || rng.next_u64() >> (64 - (size - 2));
+        let write =
+            |bitmap: &mut BitGrid, (x, y, (dx, dy)): (usize, usize, (usize, usize)), bits: u64| {
+                for k in 1..step {
+                    bitmap.set(x + k * dx, y + k * dy, bits >> (step - 1 - k) & 1 == 1);
+                }
+            };
+        let read = |bitmap: &BitGrid, (x, y, (dx, dy)): (usize, usize, (usize, usize))| {
+            (0..size).fold(0, |acc, k| {
+                acc << 1 | bitmap.get(x + k * dx, y + k * dy) as u64
+            })
         };
+        match borders {
+            Borders::Keep => {}
+            Borders::Pool(n) => {
+                let pool: Vec<u64> = (0..n.max(1)).map(|_| random()).collect();
+                for segment in segments {
+                    let bits = pool[rng.below(pool.len() as u128) as usize];
+                    write(bitmap, segment, bits);
+                }
+            }
+            Borders::Unique => {
+                let mut seen = HashSet::new();
+                for segment in segments {
+                    // Palindromes are excluded as they match themselves flipped
+                    let unique = (0..1000).any(|_| {
+                        write(bitmap, segment, random());
+                        let bits = read(bitmap, segment);
+                        let rev = reverse(bits, size);
+                        bits != rev && seen.insert(bits.min(rev))
+                    });
+                    if !unique {
+                        return Err(anyhow!(
+                            "Cannot draw {} unique borders of {} cells",
+                            seen.len() + 1,
+                            size
+                        ));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
 
-        let cnt = rotate_lookup(self);
-        if cnt > 0 {
-            return cnt;
+    /// The tiles in the format of `input_parser`
+    pub fn write_input(&self, w: &mut impl std::io::Write) -> std::io::Result<()> {
+        for (i, tile) in self.tiles.iter().enumerate() {
+            if i > 0 {
+                writeln!(w)?;
+            }
+            write!(w, "Tile {}:\n{}", tile.id, tile)?;
         }
-        self.image.flip_cols();
+        Ok(())
+    }
 
-        let cnt = rotate_lookup(self);
-        if cnt > 0 {
-            return cnt;
+    /// One line per tile with its id, true position and orientation, in the order of the input
+    pub fn write_truth(&self, w: &mut impl std::io::Write) -> std::io::Result<()> {
+        writeln!(w, "# id row col orientation")?;
+        writeln!(
+            w,
+            "# orientation: 0 to 3 quarter turns clockwise, 4 to 7 after a flip left to right"
+        )?;
+        for p in &self.pieces {
+            writeln!(w, "{} {} {} {}", p.id, p.row, p.col, p.orientation.index())?;
         }
+        Ok(())
+    }
+}
+
+/// Backtracking search over the placements of oriented tiles, in row-major order
+struct Assembler<'a> {
+    tiles: &'a [Tile],
+    index: EdgeIndex,
+}
 
-        self.image.flip_rows();
+/// (tile, orientation) at each position of the arrangement, in row-major order
+type Placement = [(usize, Orientation)];
 
-        rotate_lookup(self)
+impl<'a> Assembler<'a> {
+    fn new(tiles: &'a [Tile]) -> Self {
+        Self {
+            tiles,
+            index: EdgeIndex::new(tiles),
+        }
+    }
+
+    fn view(&self, (idx, o): (usize, Orientation)) -> TileView<'a> {
+        self.tiles[idx].view(o)
+    }
+
+    /// Oriented tiles with `border` on `side`
+    fn fitting(&self, border: u64, side: Side) -> impl Iterator<Item = (usize, Orientation)> + '_ {
+        self.index
+            .sides(border)
+            .iter()
+            .map(|(idx, _, _)| *idx)
+            .unique()
+            .flat_map(move |idx| {
+                Orientation::ALL
+                    .iter()
+                    .filter(move |o| self.view((idx, **o)).border(side) == border)
+                    .map(move |o| (idx, *o))
+            })
+    }
+
+    /// Candidates for the next position given what is already placed
+    fn candidates(&self, placed: &Placement, cols: usize) -> Vec<(usize, Orientation)> {
+        let pos = placed.len();
+        let above = pos.checked_sub(cols).map(|p| self.view(placed[p]));
+        let left = Some(pos)
+            .filter(|p| p % cols > 0)
+            .map(|p| self.view(placed[p - 1]));
+        match (left, above) {
+            (Some(left), above) => self
+                .fitting(left.border(Side::Right), Side::Left)
+                .filter(|c| {
+                    above.map_or(true, |a| {
+                        self.view(*c).border(Side::Top) == a.border(Side::Bottom)
+                    })
+                })
+                .collect(),
+            (None, Some(above)) => self
+                .fitting(above.border(Side::Bottom), Side::Top)
+                .collect(),
+            (None, None) => {
+                // Most likely top left corners first: no other tile matches their top or left
+                let mut candidates: Vec<_> = (0..self.tiles.len())
+                    .flat_map(|idx| Orientation::ALL.iter().map(move |o| (idx, *o)))
+                    .collect();
+                candidates.sort_by_cached_key(|(idx, o)| {
+                    let view = self.view((*idx, *o));
+                    self.index.others(view.border(Side::Top), *idx)
+                        + self.index.others(view.border(Side::Left), *idx)
+                });
+                candidates
+            }
+        }
+    }
+
+    /// Call `found` on every complete placement until it returns false
+    fn search(&self, rows: usize, cols: usize, found: &mut impl FnMut(&Self, &Placement) -> bool) {
+        let n = rows * cols;
+        let mut used = vec![false; self.tiles.len()];
+        let mut placed = Vec::with_capacity(n);
+        // One frame of candidates per position being filled
+        let mut stack = vec![self.candidates(&placed, cols).into_iter()];
+        while let Some(frame) = stack.last_mut() {
+            match frame.next() {
+                Some((idx, _)) if used[idx] => {}
+                Some(candidate) => {
+                    placed.push(candidate);
+                    if placed.len() == n {
+                        if !found(self, &placed) {
+                            return;
+                        }
+                        placed.pop();
+                    } else {
+                        used[candidate.0] = true;
+                        stack.push(self.candidates(&placed, cols).into_iter());
+                    }
+                }
+                None => {
+                    stack.pop();
+                    if let Some((idx, _)) = placed.pop() {
+                        used[idx] = false;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Place unused tiles next to the placed ones as long as they fit all their placed
+    /// neighbours, starting from the oriented tile `seed` at the origin. Placed tiles are
+    /// never taken back, among those fitting a position the one matching the most sides
+    /// goes there.
+    fn grow(&self, seed: (usize, Orientation)) -> HashMap<(isize, isize), (usize, Orientation)> {
+        let mut placed = HashMap::new();
+        placed.insert((0, 0), seed);
+        let mut used = vec![false; self.tiles.len()];
+        used[seed.0] = true;
+        // A copy of a placed tile would fit against it
+        let mut ids = HashSet::new();
+        ids.insert(self.tiles[seed.0].id);
+        let mut queue = VecDeque::new();
+        queue.push_back((0, 0));
+        while let Some((x, y)) = queue.pop_front() {
+            let view = self.view(placed[&(x, y)]);
+            for &(side, pos) in Self::neighbours((x, y)).iter() {
+                if placed.contains_key(&pos) {
+                    continue;
+                }
+                // Decoys sharing a border with a real tile match fewer sides than it
+                let candidate = self
+                    .fitting(view.border(side), side.opposite())
+                    .filter(|c| {
+                        !used[c.0]
+                            && !ids.contains(&self.tiles[c.0].id)
+                            && self.fits(&placed, pos, *c)
+                    })
+                    .min_by_key(|c| 4 - self.index.matched_sides(self.tiles, c.0));
+                if let Some(c) = candidate {
+                    used[c.0] = true;
+                    ids.insert(self.tiles[c.0].id);
+                    placed.insert(pos, c);
+                    queue.push_back(pos);
+                }
+            }
+        }
+        placed
+    }
+
+    /// Positions around `(x, y)`, by side
+    fn neighbours((x, y): (isize, isize)) -> [(Side, (isize, isize)); 4] {
+        [
+            (Side::Top, (x - 1, y)),
+            (Side::Bottom, (x + 1, y)),
+            (Side::Left, (x, y - 1)),
+            (Side::Right, (x, y + 1)),
+        ]
+    }
+
+    /// Whether `candidate` matches all the placed tiles around `pos`
+    fn fits(
+        &self,
+        placed: &HashMap<(isize, isize), (usize, Orientation)>,
+        pos: (isize, isize),
+        candidate: (usize, Orientation),
+    ) -> bool {
+        let view = self.view(candidate);
+        Self::neighbours(pos).iter().all(|(side, n)| {
+            placed.get(n).map_or(true, |p| {
+                self.view(*p).border(side.opposite()) == view.border(*side)
+            })
+        })
+    }
+
+    /// Grid of tile ids and full image (with borders) of a placement
+    fn grids(&self, placed: &Placement, cols: usize) -> (Vec<Vec<usize>>, BitGrid) {
+        let ids = placed
+            .chunks(cols)
+            .map(|row| row.iter().map(|p| self.view(*p).id()).collect())
+            .collect();
+        let size = self.tiles.first().map_or(0, Tile::size);
+        let mut bits = BitGrid::new(placed.len() / cols * size, cols * size);
+        for (pos, p) in placed.iter().enumerate() {
+            bits.paste(
+                pos / cols * size,
+                pos % cols * size,
+                &self.view(*p).to_grid(),
+            );
+        }
+        (ids, bits)
+    }
+
+    /// Key identifying a placement up to rotations and flips of the whole image
+    fn canonical(&self, placed: &Placement, cols: usize) -> (Vec<Vec<usize>>, BitGrid) {
+        let (ids, bits) = self.grids(placed, cols);
+        Orientation::ALL
+            .iter()
+            .map(|o| (orient(&ids, *o), bits.oriented(*o)))
+            .min()
+            .unwrap()
+    }
+
+    fn image(&self, placed: &Placement, cols: usize) -> Image {
+        let mut image = Image::default();
+        for (pos, (idx, o)) in placed.iter().enumerate() {
+            let xy = ((pos / cols) as isize, (pos % cols) as isize);
+            image.map.insert(xy, (self.tiles[*idx].clone(), *o));
+        }
+        image.stitch();
+        image
     }
 }
 
@@ -365,29 +1688,20 @@ impl Display for Image {
     }
 }
 
-#[aoc(day20, part2)]
-pub fn part2(tiles: &[Tile]) -> usize {
-    let mut image = Image::from_tiles(tiles.to_vec());
-    let sea_monster = "\
+// Any character other than `#` and `.` is a wildcard, `-` keeps the leading spaces
+pub const SEA_MONSTER: &str = "\
 -                 # 
 #    ##    ##    ###
  #  #  #  #  #  #   ";
-    let sea_monster_coords: Vec<(usize, usize)> = sea_monster
-        .split("\n")
-        .enumerate()
-        .flat_map(|(x, s)| {
-            s.chars()
-                .enumerate()
-                .filter(|(_, c)| *c == '#')
-                .map(move |(y, _)| (x, y))
-        })
-        .collect();
 
-    let count = image.transform_and_count(&sea_monster_coords);
+#[aoc(day20, part2)]
+pub fn part2(tiles: &[Tile]) -> usize {
+    let image = Image::from_tiles(tiles.to_vec()).expect("Valid assembly");
+    let patterns = [SEA_MONSTER.parse().unwrap()];
+    let matches = image.find_patterns(&patterns, true);
 
-    println!("Found {} sea monsters!", count);
-    image.to_string().chars().filter(|c| *c == '#').count()
-        - count * sea_monster.chars().filter(|c| *c == '#').count()
+    println!("Found {} sea monsters!", matches.len());
+    image.roughness(&patterns, &matches)
 }
 
 #[cfg(test)]
@@ -512,4 +1826,501 @@ Tile 3079:
     fn test_part2() {
         assert_eq!(part2(&input_parser(TESTCASE).unwrap()), 273)
     }
+
+    /// `rows`x`cols` scrambled tiles of `size`x`size` cut from a random bitmap
+    fn jigsaw(rows: usize, cols: usize, size: usize, borders: Borders, seed: u64) -> Jigsaw {
+        let bitmap = BitGrid::random(rows * (size - 1) + 1, cols * (size - 1) + 1, seed);
+        Jigsaw::cut(&bitmap, size, borders, seed).unwrap()
+    }
+
+    /// Whether `image` is the bitmap of `jigsaw` turned some way
+    fn assembles(jigsaw: &Jigsaw, image: &Image) -> bool {
+        orientations(jigsaw.image.clone()).contains(&image.image)
+    }
+
+    fn orientations(grid: BitGrid) -> Vec<BitGrid> {
+        Orientation::ALL.iter().map(|o| grid.oriented(*o)).collect()
+    }
+
+    #[test]
+    fn test_tile_sizes() {
+        for &(rows, cols, size) in &[(3, 3, 16), (2, 4, 16), (4, 3, 32)] {
+            let jigsaw = jigsaw(rows, cols, size, Borders::Unique, size as u64);
+            let image = Image::from_tiles(jigsaw.tiles.clone()).unwrap();
+            // Each tile loses its borders
+            let (height, width) = (rows * (size - 2), cols * (size - 2));
+            let dims = (image.image.rows(), image.image.cols());
+            assert!(dims == (height, width) || dims == (width, height));
+            assert!(assembles(&jigsaw, &image));
+        }
+    }
+
+    #[test]
+    fn test_orientation_group() {
+        let mut rng = crate::utils::Rng::new(3);
+        let rows: Vec<Vec<bool>> = (0..3)
+            .map(|_| (0..5).map(|_| rng.below(2) == 1).collect())
+            .collect();
+        let grid = BitGrid::from_rows(&rows);
+        assert_eq!(
+            grid.oriented(Orientation::new(1, false)),
+            BitGrid::from_rows(&orient(&rows, Orientation::new(1, false)))
+        );
+        for (idx, a) in Orientation::ALL.iter().enumerate() {
+            assert_eq!(a.index(), idx);
+            assert_eq!(grid.oriented(*a).oriented(a.inverse()), grid);
+            for &b in Orientation::ALL.iter() {
+                assert_eq!(grid.oriented(*a).oriented(b), grid.oriented(a.then(b)));
+            }
+        }
+        assert_eq!(orientations(grid).iter().unique().count(), 8);
+    }
+
+    #[test]
+    fn test_view_borders() {
+        let tile = Tile::new(0, BitGrid::random(10, 10, 7));
+        let sides = [Side::Top, Side::Bottom, Side::Left, Side::Right];
+        for &o in Orientation::ALL.iter() {
+            let view = tile.view(o);
+            let copy = Tile::new(0, view.to_grid());
+            for &side in sides.iter() {
+                assert_eq!(view.border(side), copy.borders[side as usize]);
+            }
+            let twice = view.oriented(o);
+            assert_eq!(twice.to_grid(), view.to_grid().oriented(o));
+        }
+    }
+
+    #[test]
+    fn test_bit_grid_window() {
+        let mut grid = BitGrid::new(2, 100);
+        for y in (0..100).step_by(3) {
+            grid.set(1, y, true);
+        }
+        assert_eq!(grid.window(1, 60, 8), 0b1001_0010);
+        assert_eq!(grid.window(1, 96, 8), 0b1001_0000);
+        assert_eq!(grid.window(0, 0, 64), 0);
+        assert_eq!(grid.count_ones(), 34);
+
+        grid.put(0, 60, 0b1011_0111, 8);
+        grid.put(1, 60, 0, 4);
+        assert_eq!(grid.window(0, 58, 12), 0b0010_1101_1100);
+        assert_eq!(grid.window(1, 60, 8), 0b0000_0010);
+        let mut pasted = BitGrid::new(3, 101);
+        pasted.paste(1, 1, &grid);
+        assert_eq!(pasted.region(1, 1, 2, 100), grid);
+    }
+
+    #[test]
+    fn test_invalid_tiles() {
+        assert!(input_parser("Tile 1:\n#.\n.#").is_err());
+        assert!(input_parser("Tile 1:\n#..\n.#.\n..").is_err());
+        assert!(input_parser("Tile 1:\n#..\n.#.\n...\n\nTile 2:\n#...\n.#..\n....\n....").is_err());
+        assert!(input_parser("Tile x:\n#..\n.#.\n...").is_err());
+    }
+
+    #[test]
+    fn test_ambiguous_edges() {
+        // 6x6 tiles only have 64 possible edges: some appear on more than two tiles
+        for &(seed, count) in &[(0, 1), (12, 3)] {
+            let jigsaw = jigsaw(3, 3, 6, Borders::Keep, seed);
+            let mut tiles_by_edge: HashMap<u64, HashSet<usize>> = HashMap::new();
+            for t in &jigsaw.tiles {
+                for &o in Orientation::ALL.iter() {
+                    let top = t.view(o).border(Side::Top);
+                    tiles_by_edge.entry(top).or_default().insert(t.id);
+                }
+            }
+            assert!(tiles_by_edge.values().any(|ids| ids.len() > 2));
+
+            // Backtracking finds every way to fit the tiles, the bitmap among them
+            let images = Image::assemblies(&jigsaw.tiles, usize::MAX).unwrap();
+            assert_eq!(images.len(), count);
+            assert!(images.iter().any(|i| assembles(&jigsaw, i)));
+        }
+    }
+
+    #[test]
+    fn test_assembly_error() {
+        let mut tiles = jigsaw(2, 2, 10, Borders::Unique, 1).tiles;
+        tiles.push(Tile::new(1, BitGrid::random(10, 10, 2)));
+        assert!(Image::assemblies(&tiles, 1).is_err());
+    }
+
+    #[test]
+    fn test_jigsaw() {
+        let bitmap = BitGrid::random(37, 46, 11);
+        let jigsaw = Jigsaw::cut(&bitmap, 10, Borders::Unique, 11).unwrap();
+        assert_eq!((jigsaw.tiles.len(), jigsaw.image.rows()), (20, 32));
+        let mut input = Vec::new();
+        jigsaw.write_input(&mut input).unwrap();
+        let tiles = input_parser(&String::from_utf8(input).unwrap()).unwrap();
+
+        // Turning a tile back gives its piece of the bitmap, with redrawn borders
+        for (tile, p) in tiles.iter().zip(&jigsaw.pieces) {
+            let piece = tile.view(p.orientation.inverse()).to_grid();
+            let original = bitmap.region(p.row * 9, p.col * 9, 10, 10);
+            assert_eq!(piece.region(1, 1, 8, 8), original.region(1, 1, 8, 8));
+        }
+        let corners: usize = jigsaw
+            .pieces
+            .iter()
+            .filter(|p| (p.row == 0 || p.row == 3) && (p.col == 0 || p.col == 4))
+            .map(|p| p.id)
+            .product();
+        assert_eq!(part1(&tiles), corners);
+        let images = Image::assemblies(&tiles, usize::MAX).unwrap();
+        assert_eq!(images.len(), 1);
+        assert!(assembles(&jigsaw, &images[0]));
+
+        let mut truth = Vec::new();
+        jigsaw.write_truth(&mut truth).unwrap();
+        let truth = String::from_utf8(truth).unwrap();
+        assert_eq!(truth.lines().filter(|l| !l.starts_with('#')).count(), 20);
+    }
+
+    #[test]
+    fn test_jigsaw_borders() {
+        let bitmap = BitGrid::random(16, 16, 4);
+        let tiles_by_edge = |borders| {
+            let jigsaw = Jigsaw::cut(&bitmap, 6, borders, 4).unwrap();
+            let index = EdgeIndex::new(&jigsaw.tiles);
+            index
+                .sides
+                .values()
+                .map(|sides| sides.iter().map(|(idx, _, _)| idx).unique().count())
+                .max()
+                .unwrap()
+        };
+        assert_eq!(tiles_by_edge(Borders::Unique), 2);
+        assert!(tiles_by_edge(Borders::Pool(2)) > 2);
+        assert!(Jigsaw::cut(&bitmap, 3, Borders::Unique, 4).is_err());
+        assert!(Jigsaw::cut(&bitmap, 20, Borders::Keep, 4).is_err());
+    }
+
+    #[test]
+    fn test_read_pbm() {
+        let grid = BitGrid::random(5, 80, 1);
+        let mut out = Vec::new();
+        Image {
+            image: grid.clone(),
+            ..Image::default()
+        }
+        .write_pbm(&mut out)
+        .unwrap();
+        let pbm = String::from_utf8(out).unwrap();
+        assert_eq!(BitGrid::read_pbm(&pbm).unwrap(), grid);
+        let commented = "P1 # comment\n3 2\n1 0 0\n# row\n0 1 1\n";
+        assert_eq!(
+            BitGrid::read_pbm(commented).unwrap(),
+            image("#..\n.##").image
+        );
+        assert!(BitGrid::read_pbm("P1\n3 2\n100\n01").is_err());
+        assert!(BitGrid::read_pbm("P4\n3 2\n").is_err());
+    }
+
+    #[test]
+    fn test_edge_index() {
+        let tiles = input_parser(TESTCASE).unwrap();
+        let index = EdgeIndex::new(&tiles);
+        let kinds = index.classify(&tiles);
+        let kind = |id| kinds[tiles.iter().position(|t| t.id == id).unwrap()];
+        assert_eq!(kind(1951), Some(TileKind::Corner));
+        assert_eq!(kind(2311), Some(TileKind::Border));
+        assert_eq!(kind(1427), Some(TileKind::Interior));
+        // Borders match in either direction
+        let right = tiles[3].border(Side::Right);
+        assert!(index
+            .sides(right)
+            .iter()
+            .any(|(idx, side, _)| (*idx, *side) == (3, Side::Right)));
+        assert_eq!(index.sides(right), index.sides(reverse(right, 10)));
+        assert_eq!(index.sides(right).len(), 2);
+    }
+
+    #[test]
+    fn test_large_assembly() {
+        let jigsaw = jigsaw(100, 100, 16, Borders::Unique, 9);
+        let kinds = EdgeIndex::new(&jigsaw.tiles).classify(&jigsaw.tiles);
+        let count = |kind| kinds.iter().filter(|k| **k == Some(kind)).count();
+        assert_eq!(count(TileKind::Corner), 4);
+        assert_eq!(count(TileKind::Border), 2 * (98 + 98));
+        let image = Image::from_tiles(jigsaw.tiles.clone()).unwrap();
+        assert!(assembles(&jigsaw, &image));
+    }
+
+    #[test]
+    fn test_validate() {
+        let tiles = input_parser(TESTCASE).unwrap();
+        let mut image = Image::from_tiles(tiles.clone()).unwrap();
+        assert!(image.validate(&tiles).is_valid());
+
+        let center = image.map.remove(&(1, 1)).unwrap().0.id;
+        let (corner, o) = image.map[&(0, 0)].clone();
+        image
+            .map
+            .insert((0, 0), (corner.clone(), o.then(Orientation::new(2, false))));
+        image.map.insert((-1, 0), (corner.clone(), o));
+        image.stitch();
+        let report = image.validate(&tiles);
+        let right = image.map[&(0, 1)].0.id;
+        let below = image.map[&(1, 0)].0.id;
+        for p in &[
+            Problem::Gap { row: 1, col: 1 },
+            Problem::Unplaced(center),
+            Problem::Duplicate {
+                id: corner.id,
+                count: 2,
+            },
+            Problem::Outside {
+                id: corner.id,
+                position: (-1, 0),
+            },
+            Problem::Mismatch {
+                first: corner.id,
+                second: right,
+                side: Side::Right,
+            },
+            Problem::Mismatch {
+                first: corner.id,
+                second: below,
+                side: Side::Bottom,
+            },
+        ] {
+            assert!(report.problems.contains(p), "{:?}", p);
+        }
+        // The rotated corner has its matching borders on the outside
+        assert!(report.problems.contains(&Problem::Dangling {
+            id: corner.id,
+            side: Side::Top
+        }));
+        assert_eq!(
+            report.by_tile()[&Some(center)],
+            vec![&Problem::Unplaced(center)]
+        );
+        assert!(report
+            .to_string()
+            .contains(&format!("Tile {}:\n  {} is not placed", center, center)));
+    }
+
+    #[test]
+    fn test_partial_assembly() {
+        let jigsaw = jigsaw(5, 5, 10, Borders::Unique, 21);
+        let at = |row, col| {
+            let idx = jigsaw
+                .pieces
+                .iter()
+                .position(|p| (p.row, p.col) == (row, col))
+                .unwrap();
+            jigsaw.tiles[idx].clone()
+        };
+        let (interior, border) = (at(2, 2), at(0, 3));
+        let mut tiles: Vec<Tile> = jigsaw
+            .tiles
+            .iter()
+            .filter(|t| t.id != interior.id && t.id != border.id)
+            .cloned()
+            .collect();
+        let duplicate = at(4, 1);
+        tiles.push(duplicate.clone());
+        let decoy = Tile::new(42, BitGrid::random(10, 10, 0));
+        tiles.insert(3, decoy);
+
+        let partial = Image::assemble_partial(&tiles).unwrap();
+        assert_eq!(partial.image.map.len(), 23);
+        assert_eq!(partial.unplaced.len(), 2);
+        assert!(partial.unplaced.contains(&42) && partial.unplaced.contains(&duplicate.id));
+        assert_eq!(partial.gaps.len(), 2);
+        assert_eq!(partial.image.image.rows(), 40);
+
+        // Gap cells only match wildcards
+        let cells = partial.image.image.rows() * partial.image.image.cols();
+        let found = partial
+            .image
+            .find_patterns(&[".".parse().unwrap()], true)
+            .len();
+        let gaps = 2 * 8 * 8;
+        assert_eq!(found, cells - partial.image.image.count_ones() - gaps);
+        let report = partial.image.validate(&tiles);
+        assert!(report.problems.contains(&Problem::Unplaced(42)));
+    }
+
+    #[test]
+    fn test_partial_assembly_decoy() {
+        // The decoy shares the left border of a real tile and comes first in the input,
+        // placing it first leaves 4 more tiles out
+        let jigsaw = jigsaw(5, 5, 10, Borders::Unique, 21);
+        let real = &jigsaw.tiles[3];
+        let mut bits = BitGrid::random(10, 10, 5);
+        bits.put(0, 0, real.border(Side::Left), 10);
+        let mut tiles = vec![Tile::new(42, bits)];
+        tiles.extend(jigsaw.tiles.iter().cloned());
+
+        let partial = Image::assemble_partial(&tiles).unwrap();
+        assert_eq!(partial.unplaced, vec![42]);
+        assert!(partial.gaps.is_empty());
+        assert!(assembles(&jigsaw, &partial.image));
+    }
+
+    #[test]
+    fn test_partial_assembly_seeds() {
+        // Made of four borders between real tiles, the decoy matches as many sides as an
+        // interior tile and is the first seed: growing from it shuts real tiles out
+        let jigsaw = jigsaw(4, 4, 10, Borders::Unique, 21);
+        let shared = [
+            (14, Side::Bottom),
+            (13, Side::Bottom),
+            (12, Side::Right),
+            (6, Side::Bottom),
+        ];
+        let mut bits = BitGrid::random(10, 10, 2837);
+        for (&(idx, from), &to) in shared.iter().zip(Side::ALL.iter()) {
+            let border = jigsaw.tiles[idx].border(from);
+            for i in 0..10 {
+                let (x, y) = match to {
+                    Side::Top => (0, i),
+                    Side::Bottom => (9, i),
+                    Side::Left => (i, 0),
+                    Side::Right => (i, 9),
+                };
+                bits.set(x, y, border >> (9 - i) & 1 == 1);
+            }
+        }
+        let mut tiles = vec![Tile::new(42, bits)];
+        tiles.extend(jigsaw.tiles.iter().cloned());
+        let assembler = Assembler::new(&tiles);
+        assert_eq!(assembler.index.matched_sides(&tiles, 0), 4);
+        assert_eq!(assembler.grow((0, Orientation::default())).len(), 10);
+
+        let partial = Image::assemble_partial(&tiles).unwrap();
+        assert_eq!(partial.unplaced, vec![42]);
+        assert!(partial.gaps.is_empty());
+        assert!(assembles(&jigsaw, &partial.image));
+    }
+
+    fn image(s: &str) -> Image {
+        Image {
+            image: BitGrid::from_rows(
+                &s.lines()
+                    .map(|l| l.chars().map(|c| c == '#').collect())
+                    .collect::<Vec<_>>(),
+            ),
+            ..Image::default()
+        }
+    }
+
+    #[test]
+    fn test_find_patterns() {
+        let image = Image::from_tiles(input_parser(TESTCASE).unwrap()).unwrap();
+        let patterns = [SEA_MONSTER.parse().unwrap()];
+        let matches = image.find_patterns(&patterns, false);
+        assert_eq!(matches.len(), 2);
+        assert_eq!(matches[0].orientation, matches[1].orientation);
+        assert_eq!(image.roughness(&patterns, &matches), 273);
+    }
+
+    #[test]
+    fn test_overlapping_patterns() {
+        let image = image("###\n...");
+        let patterns = ["##".parse().unwrap()];
+        let overlapping = image.find_patterns(&patterns, true);
+        assert_eq!(
+            overlapping,
+            vec![
+                Match {
+                    pattern: 0,
+                    orientation: Orientation::default(),
+                    x: 0,
+                    y: 0
+                },
+                Match {
+                    pattern: 0,
+                    orientation: Orientation::default(),
+                    x: 0,
+                    y: 1
+                }
+            ]
+        );
+        // Covered cells are only counted once
+        assert_eq!(image.roughness(&patterns, &overlapping), 0);
+        assert_eq!(image.find_patterns(&patterns, false).len(), 1);
+    }
+
+    #[test]
+    fn test_pattern_wildcards() {
+        let patterns: Vec<Pattern> = vec!["#.#".parse().unwrap(), "# #".parse().unwrap()];
+        let found = |s| -> Vec<usize> {
+            image(s)
+                .find_patterns(&patterns, true)
+                .iter()
+                .map(|m| m.pattern)
+                .collect()
+        };
+        assert_eq!(found("#.#"), vec![0, 1]);
+        assert_eq!(found("###"), vec![1]);
+        // Vertical match of the rotated pattern
+        let m = image("..#\n...\n..#").find_patterns(&patterns[..1], true);
+        assert_eq!(
+            (m[0].orientation, m[0].x, m[0].y),
+            (Orientation::new(1, false), 0, 2)
+        );
+    }
+
+    #[test]
+    fn test_write_pbm() {
+        let mut out = Vec::new();
+        image("#..\n.##").write_pbm(&mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "P1\n3 2\n100\n011\n");
+    }
+
+    #[test]
+    fn test_write_ppm() {
+        let image = Image::from_tiles(input_parser(TESTCASE).unwrap()).unwrap();
+        let patterns = [SEA_MONSTER.parse().unwrap()];
+        let monsters = image.covered_cells(&patterns, &image.find_patterns(&patterns, false));
+        let options = ExportOptions {
+            boundaries: true,
+            ids: true,
+            scale: 2,
+        };
+        let mut out = Vec::new();
+        image.write_ppm(&mut out, &monsters, &options).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let mut lines = out.lines();
+        // 3 tiles of 8 cells of 2 pixels, with 2 boundaries
+        assert_eq!(lines.next(), Some("P3"));
+        assert_eq!(lines.next(), Some("50 50"));
+        assert_eq!(lines.next(), Some("255"));
+        let pixels: Vec<_> = lines.collect();
+        assert_eq!(pixels.len(), 50 * 50);
+        assert!(pixels.contains(&"255 200 0"));
+
+        let mut out = Vec::new();
+        image
+            .write_ppm(&mut out, &monsters, &ExportOptions::default())
+            .unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("P3\n24 24\n255\n"));
+        // 15 cells per monster
+        assert_eq!(out.matches("0 200 0").count(), 2 * 15);
+    }
+
+    #[test]
+    fn test_write_ansi() {
+        let image = Image::from_tiles(input_parser(TESTCASE).unwrap()).unwrap();
+        let options = ExportOptions {
+            boundaries: true,
+            ids: true,
+            scale: 1,
+        };
+        let mut out = Vec::new();
+        image
+            .write_ansi(&mut out, &HashSet::new(), &options)
+            .unwrap();
+        let out = String::from_utf8(out).unwrap();
+        // 24 rows, 2 boundaries and 3 rows of ids
+        assert_eq!(out.lines().count(), 29);
+        assert!(out.contains("2311"));
+        assert_eq!(out.matches('|').count(), 24 * 2 + 3 * 2);
+    }
 }
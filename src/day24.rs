@@ -1,8 +1,12 @@
 use std::str::FromStr;
 
+use anyhow::{anyhow, Result};
 use aoc_runner_derive::{aoc, aoc_generator};
 use hashbrown::HashMap;
 
+use crate::hex::Hex;
+use crate::life::{Automaton, Hexagonal, Rule, Sparse};
+
 #[derive(Debug, Clone, Copy)]
 pub enum Direction {
     E,
@@ -15,49 +19,75 @@ pub enum Direction {
 
 use Direction::*;
 
+/// The variants are in the order of `Hex::DIRECTIONS`
+impl From<Direction> for Hex {
+    fn from(dir: Direction) -> Hex {
+        Hex::DIRECTIONS[dir as usize]
+    }
+}
+
+impl Direction {
+    const ALL: [Direction; 6] = [E, SE, SW, W, NW, NE];
+    /// Names of `ALL`, in lowercase
+    const NAMES: [&'static str; 6] = ["e", "se", "sw", "w", "nw", "ne"];
+}
+
+/// In any case
 impl FromStr for Direction {
-    type Err = ();
+    type Err = anyhow::Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        Ok(match s {
-            "e" => E,
-            "se" => SE,
-            "sw" => SW,
-            "w" => W,
-            "nw" => NW,
-            "ne" => NE,
-            _ => return Err(()),
-        })
+        let name = s.to_ascii_lowercase();
+        Direction::NAMES
+            .iter()
+            .position(|n| *n == name)
+            .map(|i| Direction::ALL[i])
+            .ok_or_else(|| anyhow!("Unknown direction {:?}", s))
+    }
+}
+
+/// Directions of a line, run together or separated by whitespace, in any case
+fn tokenize(line: &str) -> Result<Vec<Direction>> {
+    let mut dirs = Vec::new();
+    let mut chars = line.chars().enumerate().peekable();
+    while let Some((col, c)) = chars.next() {
+        if c.is_whitespace() {
+            continue;
+        }
+        let dir = if matches!(c.to_ascii_lowercase(), 'n' | 's') {
+            let pair = chars
+                .peek()
+                .and_then(|(_, next)| format!("{}{}", c, next).parse().ok());
+            match pair {
+                Some(dir) => {
+                    chars.next();
+                    dir
+                }
+                None => return Err(anyhow!("Dangling '{}' at column {}", c, col + 1)),
+            }
+        } else {
+            c.to_string()
+                .parse()
+                .map_err(|_| anyhow!("Unexpected '{}' at column {}", c, col + 1))?
+        };
+        dirs.push(dir);
     }
+    Ok(dirs)
 }
 
 #[aoc_generator(day24)]
-pub fn input_parser(input: &str) -> Vec<Vec<Direction>> {
+pub fn input_parser(input: &str) -> Result<Vec<Vec<Direction>>> {
     input
         .lines()
-        .map(|s| {
-            let mut i = 0;
-            let mut v = Vec::new();
-            while i < s.len() {
-                if let Ok(d) = s[i..i + 1].parse::<Direction>() {
-                    v.push(d);
-                    i += 1;
-                } else {
-                    if let Ok(d) = s[i..i + 2].parse::<Direction>() {
-                        v.push(d);
-                        i += 2;
-                    }
-                }
-            }
-            v
-        })
+        .enumerate()
+        .map(|(idx, line)| tokenize(line).map_err(|e| anyhow!("line {}: {}", idx + 1, e)))
         .collect()
 }
 
 #[derive(Debug, Default)]
 pub struct Grid {
-    cur: (isize, isize),
-    map: HashMap<(isize, isize), bool>,
+    cur: Hex,
+    map: HashMap<Hex, bool>,
 }
 
 impl Grid {
@@ -76,37 +106,12 @@ impl Grid {
             .or_insert(true);
     }
 
-    fn step_xy((mut x, mut y): (isize, isize), dir: &Direction) -> (isize, isize) {
-        match dir {
-            E => x += 2,
-            SE => {
-                x += 1;
-                y += 1
-            }
-            SW => {
-                x -= 1;
-                y += 1;
-            }
-            W => x -= 2,
-
-            NW => {
-                x -= 1;
-                y -= 1;
-            }
-            NE => {
-                x += 1;
-                y -= 1;
-            }
-        }
-        (x, y)
-    }
-
     fn step(&mut self, dir: &Direction) {
-        self.cur = Self::step_xy(self.cur, dir);
+        self.cur = self.cur + Hex::from(*dir);
     }
 
     fn switch_tile(&mut self, dirs: &[Direction]) {
-        self.cur = (0, 0);
+        self.cur = Hex::ORIGIN;
         dirs.iter().for_each(|dir| self.step(dir));
         self.switch_cur_tile()
     }
@@ -115,35 +120,16 @@ impl Grid {
         self.map.values().filter(|v| **v).count()
     }
 
-    const NEIGHBORS_DIRS: [Direction; 6] = [E, SE, SW, W, NW, NE];
-
-    fn neighbors(xy: (isize, isize)) -> impl Iterator<Item = (isize, isize)> {
-        Self::NEIGHBORS_DIRS
+    /// Black tiles with 0 or more than 2 black neighbours flip to white,
+    /// white tiles with exactly 2 black neighbours flip to black
+    fn days(&self, n: usize) -> usize {
+        let black = self
+            .map
             .iter()
-            .map(move |d| Self::step_xy(xy, d))
-    }
-
-    fn daily_flip(&mut self) {
-        let mut black_neighbors_counter: HashMap<_, usize> = HashMap::new();
-        for (&cur_black_tile, _) in self.map.iter().filter(|(_, black)| **black) {
-            black_neighbors_counter.entry(cur_black_tile).or_default();
-
-            Self::neighbors(cur_black_tile).for_each(|neighbor| {
-                *black_neighbors_counter.entry(neighbor).or_default() += 1;
-            })
-        }
-        for (xy, cnt) in black_neighbors_counter.drain() {
-            match self.map.get(&xy) {
-                Some(true) if cnt == 0 || cnt > 2 => self.map.insert(xy, false),
-                Some(false) | None if cnt == 2 => self.map.insert(xy, true),
-                _ => None,
-            };
-        }
-    }
-
-    fn days(&mut self, n: usize) -> usize {
-        (0..n).for_each(|_| self.daily_flip());
-        self.count_black()
+            .filter(|(_, black)| **black)
+            .map(|(hex, _)| *hex);
+        let mut life = Sparse::new(Hexagonal, Rule::new(&[2], &[1, 2]), black).unwrap();
+        life.run(n)
     }
 }
 
@@ -185,11 +171,31 @@ wseweeenwnesenwwwswnew";
 
     #[test]
     fn test_part1() {
-        assert_eq!(part1(&input_parser(TESTCASE)), 10)
+        assert_eq!(part1(&input_parser(TESTCASE).unwrap()), 10)
     }
 
     #[test]
     fn test_part2() {
-        assert_eq!(part2(&input_parser(TESTCASE)), 2208)
+        assert_eq!(part2(&input_parser(TESTCASE).unwrap()), 2208)
+    }
+
+    #[test]
+    fn test_parser() {
+        let parse = |s: &str| format!("{:?}", input_parser(s).unwrap());
+        assert_eq!(parse("esenee\nNWwSW"), "[[E, SE, NE, E], [NW, W, SW]]");
+        assert_eq!(parse("se NE\tw \n"), parse("senew"));
+        let err = |s| input_parser(s).unwrap_err().to_string();
+        assert_eq!(err("esew\nnwx"), "line 2: Unexpected 'x' at column 3");
+        assert_eq!(err("eseS"), "line 1: Dangling 'S' at column 4");
+        assert_eq!(err("n e"), "line 1: Dangling 'n' at column 1");
+        assert_eq!(err("nn"), "line 1: Dangling 'n' at column 1");
+        assert_eq!(err("wé"), "line 1: Unexpected 'é' at column 2");
+        // Single directions read the same either way
+        for name in Direction::NAMES.iter() {
+            let upper = name.to_ascii_uppercase();
+            let dir = format!("{:?}", upper.parse::<Direction>().unwrap());
+            assert_eq!(parse(&upper), format!("[[{}]]", dir));
+        }
+        assert!("x".parse::<Direction>().is_err());
     }
 }
@@ -0,0 +1,248 @@
+//! Coordinates on a grid of pointy-topped hexagons, the floor of day 24: rows of
+//! hexagons run east to west, each row shifted half a hexagon from the one above.
+//! See <https://www.redblobgames.com/grids/hexagons/> for the systems used here.
+
+use std::ops::{Add, Mul, Neg, Sub};
+
+/// Axial coordinates: `q` grows to the east, `r` to the south-east. The third cube
+/// coordinate `s = -q - r` grows to the north-west.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Hex {
+    pub q: isize,
+    pub r: isize,
+}
+
+impl Hex {
+    pub const ORIGIN: Hex = Hex { q: 0, r: 0 };
+
+    /// Neighbour offsets clockwise from the east: E, SE, SW, W, NW, NE
+    pub const DIRECTIONS: [Hex; 6] = [
+        Hex { q: 1, r: 0 },
+        Hex { q: 0, r: 1 },
+        Hex { q: -1, r: 1 },
+        Hex { q: -1, r: 0 },
+        Hex { q: 0, r: -1 },
+        Hex { q: 1, r: -1 },
+    ];
+
+    pub fn new(q: isize, r: isize) -> Self {
+        Self { q, r }
+    }
+
+    pub fn s(self) -> isize {
+        -self.q - self.r
+    }
+
+    /// `(q, r, s)`, summing to 0
+    pub fn cube(self) -> (isize, isize, isize) {
+        (self.q, self.r, self.s())
+    }
+
+    pub fn from_cube(q: isize, r: isize, s: isize) -> Option<Self> {
+        Some(Self { q, r }).filter(|_| q + r + s == 0)
+    }
+
+    /// Doubled width `(col, row)`: east is 2 columns away, south-east 1 column and
+    /// 1 row, so `col + row` is even
+    pub fn doubled(self) -> (isize, isize) {
+        (2 * self.q + self.r, self.r)
+    }
+
+    pub fn from_doubled(col: isize, row: isize) -> Option<Self> {
+        Some(Self::new((col - row).div_euclid(2), row)).filter(|_| (col + row) % 2 == 0)
+    }
+
+    /// Offset `(col, row)` with odd rows shifted half a hexagon to the east
+    pub fn offset(self) -> (isize, isize) {
+        (self.q + (self.r - (self.r & 1)) / 2, self.r)
+    }
+
+    pub fn from_offset(col: isize, row: isize) -> Self {
+        Self::new(col - (row - (row & 1)) / 2, row)
+    }
+
+    pub fn neighbours(self) -> impl Iterator<Item = Hex> {
+        Self::DIRECTIONS.iter().map(move |d| self + *d)
+    }
+
+    /// Steps from the origin
+    pub fn length(self) -> usize {
+        let (q, r, s) = self.cube();
+        ((q.abs() + r.abs() + s.abs()) / 2) as usize
+    }
+
+    /// Steps between the two
+    pub fn distance(self, other: Hex) -> usize {
+        (self - other).length()
+    }
+
+    /// Rotated around the origin by `sixths` of a turn clockwise, counterclockwise
+    /// when negative
+    pub fn rotate(self, sixths: isize) -> Self {
+        (0..sixths.rem_euclid(6)).fold(self, |hex, _| {
+            let (_, r, s) = hex.cube();
+            Self::new(-r, -s)
+        })
+    }
+
+    /// Rotated around `center` by `sixths` of a turn clockwise
+    pub fn rotate_around(self, center: Hex, sixths: isize) -> Self {
+        center + (self - center).rotate(sixths)
+    }
+
+    /// The `6 * radius` hexagons at `radius` steps, clockwise from the north-west
+    /// corner, or the hexagon itself at radius 0
+    pub fn ring(self, radius: usize) -> Vec<Hex> {
+        if radius == 0 {
+            return vec![self];
+        }
+        let mut hex = self + Self::DIRECTIONS[4] * radius as isize;
+        let mut ring = Vec::with_capacity(6 * radius);
+        for direction in Self::DIRECTIONS.iter() {
+            for _ in 0..radius {
+                ring.push(hex);
+                hex = hex + *direction;
+            }
+        }
+        ring
+    }
+
+    /// The hexagons within `radius` steps, ring after ring from the hexagon itself
+    pub fn spiral(self, radius: usize) -> Vec<Hex> {
+        (0..=radius).flat_map(|r| self.ring(r)).collect()
+    }
+
+    /// The hexagons on the straight line to `other`, ends included, each one step from
+    /// the last. Ties between two hexagons go the same way along the whole line.
+    pub fn line_to(self, other: Hex) -> Vec<Hex> {
+        let n = self.distance(other);
+        // Nudged off the edges between hexagons
+        let cube = |hex: Hex| {
+            let (q, r, s) = hex.cube();
+            (q as f64 + 1e-6, r as f64 + 2e-6, s as f64 - 3e-6)
+        };
+        let (a, b) = (cube(self), cube(other));
+        (0..=n)
+            .map(|i| {
+                let t = if n == 0 { 0.0 } else { i as f64 / n as f64 };
+                let lerp = |a: f64, b: f64| a + (b - a) * t;
+                Self::round(lerp(a.0, b.0), lerp(a.1, b.1), lerp(a.2, b.2))
+            })
+            .collect()
+    }
+
+    /// Hexagon holding the fractional cube coordinates
+    fn round(q: f64, r: f64, s: f64) -> Self {
+        let (rq, rr, rs) = (q.round(), r.round(), s.round());
+        let (dq, dr, ds) = ((rq - q).abs(), (rr - r).abs(), (rs - s).abs());
+        // Fix the coordinate rounded the furthest so the three sum to 0
+        if dq > dr && dq > ds {
+            Self::new((-rr - rs) as isize, rr as isize)
+        } else if dr > ds {
+            Self::new(rq as isize, (-rq - rs) as isize)
+        } else {
+            Self::new(rq as isize, rr as isize)
+        }
+    }
+}
+
+impl Add for Hex {
+    type Output = Hex;
+
+    fn add(self, other: Hex) -> Hex {
+        Hex::new(self.q + other.q, self.r + other.r)
+    }
+}
+
+impl Sub for Hex {
+    type Output = Hex;
+
+    fn sub(self, other: Hex) -> Hex {
+        Hex::new(self.q - other.q, self.r - other.r)
+    }
+}
+
+impl Neg for Hex {
+    type Output = Hex;
+
+    fn neg(self) -> Hex {
+        Hex::new(-self.q, -self.r)
+    }
+}
+
+impl Mul<isize> for Hex {
+    type Output = Hex;
+
+    fn mul(self, k: isize) -> Hex {
+        Hex::new(self.q * k, self.r * k)
+    }
+}
+
+#[cfg(test)]
+mod test_hex {
+    use super::*;
+
+    #[test]
+    fn test_conversions() {
+        for hex in Hex::new(2, -1).spiral(3) {
+            let (q, r, s) = hex.cube();
+            assert_eq!(Hex::from_cube(q, r, s), Some(hex));
+            let (col, row) = hex.doubled();
+            assert_eq!(Hex::from_doubled(col, row), Some(hex));
+            let (col, row) = hex.offset();
+            assert_eq!(Hex::from_offset(col, row), hex);
+        }
+        assert_eq!(Hex::from_cube(1, 1, 1), None);
+        assert_eq!(Hex::from_doubled(1, 0), None);
+        // South-east then south-west lands on the row below, straight down in offsets
+        let below = Hex::DIRECTIONS[1] + Hex::DIRECTIONS[2];
+        assert_eq!(below.doubled(), (0, 2));
+        assert_eq!(below.offset(), (0, 2));
+        assert_eq!(Hex::DIRECTIONS[1].offset(), (0, 1));
+    }
+
+    #[test]
+    fn test_rings() {
+        let center = Hex::new(-3, 5);
+        for radius in 0..5 {
+            let ring = center.ring(radius);
+            assert_eq!(ring.len(), (6 * radius).max(1));
+            assert!(ring.iter().all(|h| h.distance(center) == radius));
+            // Each hexagon is next to the one before
+            for (a, b) in ring.iter().zip(ring.iter().cycle().skip(1)) {
+                assert!(radius == 0 || a.distance(*b) == 1);
+            }
+        }
+        let spiral = center.spiral(3);
+        assert_eq!(spiral.len(), 1 + 3 * 3 * 4);
+        assert_eq!(spiral[0], center);
+    }
+
+    #[test]
+    fn test_line() {
+        let (a, b) = (Hex::new(-2, 3), Hex::new(4, -1));
+        let line = a.line_to(b);
+        assert_eq!(line.len(), a.distance(b) + 1);
+        assert_eq!((line[0], *line.last().unwrap()), (a, b));
+        assert!(line.windows(2).all(|w| w[0].distance(w[1]) == 1));
+        assert_eq!(a.line_to(a), vec![a]);
+        let east: Vec<_> = (0..4).map(|q| Hex::new(q, 0)).collect();
+        assert_eq!(Hex::ORIGIN.line_to(Hex::new(3, 0)), east);
+    }
+
+    #[test]
+    fn test_rotate() {
+        let hex = Hex::new(3, -1);
+        assert_eq!(Hex::DIRECTIONS[0].rotate(1), Hex::DIRECTIONS[1]);
+        assert_eq!(Hex::DIRECTIONS[0].rotate(-1), Hex::DIRECTIONS[5]);
+        assert_eq!(hex.rotate(6), hex);
+        assert_eq!(hex.rotate(3), -hex);
+        assert_eq!(hex.rotate(2).rotate(-2), hex);
+        assert!((0..6).all(|k| hex.rotate(k).length() == hex.length()));
+        let center = Hex::new(1, 1);
+        assert_eq!(
+            hex.rotate_around(center, 1).distance(center),
+            hex.distance(center)
+        );
+    }
+}
@@ -1,5 +1,7 @@
 #[macro_use]
 mod utils;
+pub mod hex;
+pub mod life;
 
 pub mod day1;
 pub mod day2;
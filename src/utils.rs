@@ -13,3 +13,33 @@
         };
     }
 
+/// SplitMix64: small seeded generator for reproducible sampling without pulling `rand`
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in `0..n`
+    pub fn below(&mut self, n: u128) -> u128 {
+        assert!(n > 0, "Empty range");
+        // Reject the last incomplete block of `n` values to avoid modulo bias
+        let zone = u128::MAX - (u128::MAX - n + 1) % n;
+        loop {
+            let x = (self.next_u64() as u128) << 64 | self.next_u64() as u128;
+            if x <= zone {
+                return x % n;
+            }
+        }
+    }
+}